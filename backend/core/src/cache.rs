@@ -0,0 +1,375 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+
+/// The default upper bound for the total size of the cache.
+const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Files that haven't been used for this long are removed by default.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+static CACHE_INSTANCE: OnceCell<Cache> = OnceCell::new();
+
+type Clock = Box<dyn Fn() -> SystemTime + Send + Sync>;
+
+/// A file cache where each file is named by the hash of its contents.
+///
+/// When the total size of the cache grows beyond its limit, the least recently used files are removed.
+/// Files that haven't been used for longer than the age limit are removed as well.
+///
+/// Files can be owned by something that uses them, like a notification. Owned files are never
+/// evicted, and are removed as soon as the last of their owners releases them.
+pub struct Cache {
+    root: PathBuf,
+    max_size: u64,
+    max_age: Duration,
+    clock: Clock,
+    owners: Mutex<HashMap<PathBuf, HashSet<String>>>,
+}
+
+impl Cache {
+    pub fn new(root: PathBuf, max_size: u64, max_age: Duration) -> Self {
+        Self {
+            root,
+            max_size,
+            max_age,
+            clock: Box::new(SystemTime::now),
+            owners: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces the clock that is used to decide which files are the oldest.
+    #[cfg(test)]
+    fn with_clock(mut self, clock: impl Fn() -> SystemTime + Send + Sync + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn global() -> &'static Self {
+        CACHE_INSTANCE.get_or_init(|| {
+            let mut root = std::env::temp_dir();
+            root.push("Wormhole");
+            root.push("cache");
+            Self::new(root, DEFAULT_MAX_SIZE, DEFAULT_MAX_AGE)
+        })
+    }
+
+    /// Stores the data in the cache and returns the path of the cached file.
+    pub fn insert(&self, data: &[u8], extension: &str) -> io::Result<PathBuf> {
+        self.store(data, extension, None)
+    }
+
+    /// Stores the data in the cache, and keeps it there until `owner` releases it.
+    pub fn insert_owned(&self, owner: &str, data: &[u8], extension: &str) -> io::Result<PathBuf> {
+        self.store(data, extension, Some(owner))
+    }
+
+    /// Marks a cached file as recently used, so that it is evicted last.
+    pub fn touch(&self, path: &Path) -> io::Result<()> {
+        fs::File::options()
+            .append(true)
+            .open(path)?
+            .set_modified((self.clock)())
+    }
+
+    /// Keeps a file that is already in the cache until `owner` releases it, and marks it as
+    /// recently used. Fails if the file has been evicted.
+    pub fn own(&self, path: &Path, owner: &str) -> io::Result<()> {
+        // files aren't evicted or removed while the lock is held.
+        let mut owners = self.owners.lock().unwrap();
+        self.touch(path)?;
+        owners
+            .entry(path.to_path_buf())
+            .or_default()
+            .insert(owner.to_string());
+        Ok(())
+    }
+
+    fn store(&self, data: &[u8], extension: &str, owner: Option<&str>) -> io::Result<PathBuf> {
+        let mut path = self.root.join(format!("{:x}", Sha256::digest(data)));
+        if !extension.is_empty() {
+            path.set_extension(extension);
+        }
+
+        // the owner is added first, such that the file can't be evicted by anyone else.
+        if let Some(owner) = owner {
+            self.owners
+                .lock()
+                .unwrap()
+                .entry(path.clone())
+                .or_default()
+                .insert(owner.to_string());
+        }
+
+        if path.is_file() {
+            self.touch(&path)?;
+        } else {
+            fs::create_dir_all(&self.root)?;
+
+            // write to a temporary file first, such that a partially written file is never visible.
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, data)?;
+            fs::File::options()
+                .append(true)
+                .open(&tmp_path)?
+                .set_modified((self.clock)())?;
+            fs::rename(&tmp_path, &path)?;
+        }
+
+        self.trim(&path)?;
+
+        Ok(path)
+    }
+
+    /// Releases the files owned by `owner`, and removes the ones that aren't owned by anyone else.
+    pub fn release(&self, owner: &str) -> io::Result<()> {
+        // the lock is held until the files are gone, such that they can't be owned again first.
+        let mut owners = self.owners.lock().unwrap();
+        let mut unused = Vec::new();
+        owners.retain(|path, owners| {
+            owners.remove(owner);
+            if owners.is_empty() {
+                unused.push(path.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        for path in unused {
+            log::debug!("removing {} from cache", path.display());
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes files that are too old, and then the least recently used files until the cache fits
+    /// within its size limit.
+    fn trim(&self, keep: &Path) -> io::Result<()> {
+        let owners = self.owners.lock().unwrap();
+        let now = (self.clock)();
+
+        let mut entries = Vec::new();
+        let mut total_size = 0;
+
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            total_size += metadata.len();
+            entries.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+
+        entries.sort();
+
+        for (modified, size, path) in entries {
+            let too_old = now
+                .duration_since(modified)
+                .is_ok_and(|age| age > self.max_age);
+            if total_size <= self.max_size && !too_old {
+                continue;
+            }
+            if path == keep || owners.contains_key(&path) {
+                continue;
+            }
+            log::debug!("evicting {} from cache", path.display());
+            // the file may be in use by Windows, in which case it is evicted later.
+            match fs::remove_file(&path) {
+                Ok(()) => total_size -= size,
+                Err(err) => log::warn!("failed to evict {}: {}", path.display(), err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// A clock that only moves when told to.
+    #[derive(Clone)]
+    struct TestClock(Arc<Mutex<SystemTime>>);
+
+    impl TestClock {
+        fn new() -> Self {
+            Self(Arc::new(Mutex::new(SystemTime::UNIX_EPOCH + 1000 * HOUR)))
+        }
+
+        fn advance(&self, duration: Duration) {
+            *self.0.lock().unwrap() += duration;
+        }
+
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn cache(root: &Path, max_size: u64, max_age: Duration, clock: &TestClock) -> Cache {
+        let clock = clock.clone();
+        Cache::new(root.to_path_buf(), max_size, max_age).with_clock(move || clock.now())
+    }
+
+    #[test]
+    fn test_content_addressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1024, HOUR, &TestClock::new());
+
+        let a = cache.insert(b"avatar", "png").unwrap();
+        let b = cache.insert(b"avatar", "png").unwrap();
+        let c = cache.insert(b"other", "png").unwrap();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.extension().unwrap(), "png");
+        assert_eq!(fs::read(&a).unwrap(), b"avatar");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = TestClock::new();
+        let cache = cache(dir.path(), 10, HOUR, &clock);
+
+        let a = cache.insert(b"aaaa", "").unwrap();
+        clock.advance(Duration::from_secs(1));
+        let b = cache.insert(b"bbbb", "").unwrap();
+        clock.advance(Duration::from_secs(1));
+        // using a again makes b the least recently used.
+        cache.insert(b"aaaa", "").unwrap();
+        clock.advance(Duration::from_secs(1));
+        let c = cache.insert(b"cccc", "").unwrap();
+
+        assert!(a.is_file());
+        assert!(!b.exists());
+        assert!(c.is_file());
+    }
+
+    #[test]
+    fn test_touch() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = TestClock::new();
+        let cache = cache(dir.path(), 10, HOUR, &clock);
+
+        let a = cache.insert(b"aaaa", "").unwrap();
+        clock.advance(Duration::from_secs(1));
+        let b = cache.insert(b"bbbb", "").unwrap();
+        clock.advance(Duration::from_secs(1));
+        cache.touch(&a).unwrap();
+        clock.advance(Duration::from_secs(1));
+        cache.insert(b"cccc", "").unwrap();
+
+        assert!(a.is_file());
+        assert!(!b.exists());
+        assert!(cache.touch(&b).is_err());
+    }
+
+    #[test]
+    fn test_newest_file_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 4, HOUR, &TestClock::new());
+
+        let large = cache.insert(b"larger than the cache", "").unwrap();
+        assert!(large.is_file());
+    }
+
+    #[test]
+    fn test_age_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = TestClock::new();
+        let cache = cache(dir.path(), 1024, 2 * HOUR, &clock);
+
+        let old = cache.insert(b"old", "").unwrap();
+        clock.advance(HOUR);
+        let used = cache.insert(b"used", "").unwrap();
+        clock.advance(HOUR);
+        cache.insert(b"used", "").unwrap();
+        clock.advance(HOUR);
+        let new = cache.insert(b"new", "").unwrap();
+
+        assert!(!old.exists());
+        assert!(used.is_file());
+        assert!(new.is_file());
+    }
+
+    #[test]
+    fn test_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1024, HOUR, &TestClock::new());
+
+        let shared = cache.insert_owned("1", b"avatar", "png").unwrap();
+        cache.insert_owned("2", b"avatar", "png").unwrap();
+        let own = cache.insert_owned("2", b"picture", "png").unwrap();
+        let unowned = cache.insert(b"icon", "png").unwrap();
+
+        cache.release("2").unwrap();
+        assert!(shared.is_file());
+        assert!(!own.exists());
+
+        cache.release("1").unwrap();
+        assert!(!shared.exists());
+        assert!(unowned.is_file());
+
+        // releasing twice is fine.
+        cache.release("1").unwrap();
+    }
+
+    #[test]
+    fn test_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 1024, HOUR, &TestClock::new());
+
+        let fetched = cache.insert(b"image", "png").unwrap();
+        cache.own(&fetched, "1").unwrap();
+        cache.own(&fetched, "2").unwrap();
+
+        // the file stays until the last notification that uses it is gone.
+        cache.release("1").unwrap();
+        assert!(fetched.is_file());
+        cache.release("2").unwrap();
+        assert!(!fetched.exists());
+
+        // files that were evicted have to be inserted again.
+        assert!(cache.own(&fetched, "3").is_err());
+        cache.release("3").unwrap();
+        assert!(!fetched.exists());
+    }
+
+    #[test]
+    fn test_owned_files_are_not_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = TestClock::new();
+        let cache = cache(dir.path(), 4, HOUR, &clock);
+
+        let owned = cache.insert_owned("1", b"aaaa", "").unwrap();
+        clock.advance(2 * HOUR);
+        let other = cache.insert(b"bbbb", "").unwrap();
+
+        assert!(owned.is_file());
+        assert!(other.is_file());
+    }
+}
@@ -0,0 +1,7 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+pub mod cache;
+pub mod notifications;
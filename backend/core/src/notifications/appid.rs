@@ -0,0 +1,336 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Gives each Linux application its own identity in the Action Center, by registering an
+//! AppUserModelID for it.
+//!
+//! See https://docs.microsoft.com/en-us/windows/apps/design/shell/tiles-and-notifications/send-local-toast-other-apps
+
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// The AppUserModelID of toasts that don't belong to a known application.
+pub const DEFAULT_APP_ID: &str = "johnio.wormhole.0.1";
+
+/// Where AppUserModelIDs are registered, under `HKEY_CURRENT_USER`.
+const REGISTRY_KEY: &str = "Software\\Classes\\AppUserModelId";
+
+const PREFIX: &str = "Wormhole";
+
+/// The longest AppUserModelID that Windows accepts.
+const MAX_LENGTH: usize = 128;
+
+/// The icon formats that can be used in the Action Center.
+const ICON_EXTENSIONS: &[&str] = &["png", "ico"];
+
+/// The parts of a `.desktop` file that are shown in the Action Center.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopEntry {
+    pub name: String,
+    /// An icon name from the icon theme, or an absolute path.
+    pub icon: Option<String>,
+}
+
+impl DesktopEntry {
+    /// Parses the `[Desktop Entry]` group of a `.desktop` file, or returns `None` if it has no name.
+    pub fn parse(contents: &str) -> Option<Self> {
+        let mut in_group = false;
+        let mut name = None;
+        let mut icon = None;
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_group = line == "[Desktop Entry]";
+                continue;
+            }
+            if !in_group {
+                continue;
+            }
+
+            // localized keys such as Name[de] are ignored, since they don't match.
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim_end(), unescape(value.trim_start())),
+                None => continue,
+            };
+            match key {
+                "Name" => name = Some(value),
+                "Icon" => icon = Some(value).filter(|icon| !icon.is_empty()),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            name: name.filter(|name| !name.is_empty())?,
+            icon,
+        })
+    }
+}
+
+/// Replaces the escape sequences of a string value.
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Returns the AppUserModelID of an application in a distro.
+///
+/// Characters that can't be used in an AppUserModelID are replaced, and ids that would be too long
+/// are hashed instead.
+pub fn app_user_model_id(distro: &str, desktop_entry: &str) -> String {
+    let sanitize = |s: &str| {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>()
+    };
+
+    let id = format!(
+        "{}.{}.{}",
+        PREFIX,
+        sanitize(distro),
+        desktop_entry
+            .split('.')
+            .map(sanitize)
+            .collect::<Vec<_>>()
+            .join(".")
+    );
+    if id.len() <= MAX_LENGTH {
+        return id;
+    }
+
+    let hash = Sha256::new()
+        .chain_update(distro)
+        .chain_update([0])
+        .chain_update(desktop_entry)
+        .finalize();
+    format!("{}.{:x}", PREFIX, hash)[..(PREFIX.len() + 1 + 32)].to_string()
+}
+
+/// Returns the name of the copy of an application's icon, given the path of the icon in the distro.
+///
+/// The copy is named after the AppUserModelID, which has no path separators and differs between
+/// distros, such that applications with the same desktop entry don't overwrite each other's icons.
+pub fn icon_file_name(app_id: &str, icon_path: &str) -> String {
+    let extension = icon_path
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension)
+        .filter(|extension| {
+            !extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+
+    match extension {
+        Some(extension) => format!("{}.{}", app_id, extension),
+        None => app_id.to_string(),
+    }
+}
+
+/// Checks whether the icon can be shown in the Action Center.
+pub fn is_supported_icon(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ICON_EXTENSIONS
+                .iter()
+                .any(|supported| ext.eq_ignore_ascii_case(supported))
+        })
+}
+
+/// The registry key and values that register an AppUserModelID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+    pub app_id: String,
+    /// The key under `HKEY_CURRENT_USER`.
+    pub key: String,
+    /// The string values of the key.
+    pub values: Vec<(&'static str, String)>,
+}
+
+impl Registration {
+    /// Returns the registration of an application, with the path of its icon on Windows.
+    pub fn new(
+        distro: &str,
+        desktop_entry: &str,
+        entry: &DesktopEntry,
+        icon: Option<&Path>,
+    ) -> Self {
+        let app_id = app_user_model_id(distro, desktop_entry);
+
+        // the same application may be installed in more than one distro.
+        let mut values = vec![("DisplayName", format!("{} ({})", entry.name, distro))];
+        if let Some(icon) = icon.filter(|icon| is_supported_icon(icon)) {
+            values.push(("IconUri", icon.to_string_lossy().into_owned()));
+        }
+
+        Self {
+            key: format!("{}\\{}", REGISTRY_KEY, app_id),
+            app_id,
+            values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let entry = DesktopEntry::parse(
+            "# a comment\n\
+             [Desktop Entry]\n\
+             Type=Application\n\
+             Name[de]=Rechner\n\
+             Name = Calculator\\sPlus\n\
+             Icon=org.gnome.Calculator\n\
+             \n\
+             [Desktop Action new-window]\n\
+             Name=New Window\n\
+             Icon=window-new\n",
+        );
+        assert_eq!(
+            entry,
+            Some(DesktopEntry {
+                name: String::from("Calculator Plus"),
+                icon: Some(String::from("org.gnome.Calculator")),
+            })
+        );
+
+        assert_eq!(
+            DesktopEntry::parse("[Desktop Entry]\nName=Terminal\nIcon=\n"),
+            Some(DesktopEntry {
+                name: String::from("Terminal"),
+                icon: None,
+            })
+        );
+        assert_eq!(
+            DesktopEntry::parse("[Desktop Action new]\nName=New\n"),
+            None
+        );
+        assert_eq!(DesktopEntry::parse("[Desktop Entry]\nName=\n"), None);
+    }
+
+    #[test]
+    fn test_app_user_model_id() {
+        assert_eq!(
+            app_user_model_id("Ubuntu-20.04", "org.gnome.Calculator"),
+            "Wormhole.Ubuntu-20_04.org.gnome.Calculator"
+        );
+        assert_eq!(
+            app_user_model_id("Debian", "my app..desktop"),
+            "Wormhole.Debian.my_app..desktop"
+        );
+
+        let long = app_user_model_id("Debian", &"a".repeat(200));
+        assert_eq!(long.len(), "Wormhole.".len() + 32);
+        assert!(long.starts_with("Wormhole."));
+        assert_ne!(long, app_user_model_id("Ubuntu", &"a".repeat(200)));
+    }
+
+    #[test]
+    fn test_icon_file_name() {
+        let app_id = app_user_model_id("Ubuntu", "org.gnome.Calculator");
+        assert_eq!(
+            icon_file_name(
+                &app_id,
+                "/usr/share/icons/hicolor/256x256/apps/calculator.png"
+            ),
+            "Wormhole.Ubuntu.org.gnome.Calculator.png"
+        );
+        // the same application in another distro gets its own icon.
+        assert_ne!(
+            icon_file_name(
+                &app_user_model_id("Debian", "org.gnome.Calculator"),
+                "/usr/share/icons/hicolor/256x256/apps/calculator.png"
+            ),
+            icon_file_name(
+                &app_id,
+                "/usr/share/icons/hicolor/256x256/apps/calculator.png"
+            )
+        );
+        assert_eq!(
+            icon_file_name(&app_id, "/usr/share/pixmaps/calculator"),
+            "Wormhole.Ubuntu.org.gnome.Calculator"
+        );
+        assert_eq!(
+            icon_file_name(&app_id, "/usr/share/icons.d/calculator"),
+            "Wormhole.Ubuntu.org.gnome.Calculator"
+        );
+        assert_eq!(
+            icon_file_name(&app_id, "/tmp/icon.p\\..\\ng"),
+            "Wormhole.Ubuntu.org.gnome.Calculator"
+        );
+
+        // desktop entries can't be used to leave the icons directory.
+        let app_id = app_user_model_id("Ubuntu", "..\\..\\evil");
+        let name = icon_file_name(&app_id, "/tmp/evil.png");
+        assert!(!name.contains('\\') && !name.contains('/'), "{}", name);
+        assert!(name.starts_with("Wormhole."));
+    }
+
+    #[test]
+    fn test_registration() {
+        let entry = DesktopEntry {
+            name: String::from("Calculator"),
+            icon: Some(String::from("accessories-calculator")),
+        };
+
+        assert_eq!(
+            Registration::new(
+                "Ubuntu",
+                "org.gnome.Calculator",
+                &entry,
+                Some(Path::new("C:\\Cache\\calculator.png"))
+            ),
+            Registration {
+                app_id: String::from("Wormhole.Ubuntu.org.gnome.Calculator"),
+                key: String::from(
+                    "Software\\Classes\\AppUserModelId\\Wormhole.Ubuntu.org.gnome.Calculator"
+                ),
+                values: vec![
+                    ("DisplayName", String::from("Calculator (Ubuntu)")),
+                    ("IconUri", String::from("C:\\Cache\\calculator.png")),
+                ],
+            }
+        );
+
+        // the Action Center can't show svg icons.
+        let registration = Registration::new(
+            "Ubuntu",
+            "org.gnome.Calculator",
+            &entry,
+            Some(Path::new("C:\\Cache\\calculator.svg")),
+        );
+        assert_eq!(
+            registration.values,
+            [("DisplayName", String::from("Calculator (Ubuntu)"))]
+        );
+    }
+}
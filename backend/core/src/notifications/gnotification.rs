@@ -0,0 +1,331 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Parses the notifications that are sent to the notification portal.
+//!
+//! These are the `a{sv}` dictionaries that GNotification serializes, see
+//! https://flatpak.github.io/xdg-desktop-portal/#gdbus-org.freedesktop.portal.Notification
+
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+
+use anyhow::{bail, Context};
+use zvariant::{OwnedValue, Value};
+
+use super::timing::Urgency;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Icon {
+    /// Icon names from the icon theme, in order of preference.
+    Themed(Vec<String>),
+    /// The contents of an image file.
+    Bytes(Vec<u8>),
+    File(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Action {
+    /// The name of the action, including the `app.` prefix.
+    pub name: String,
+    pub target: Option<OwnedValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Button {
+    pub label: String,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GNotification {
+    pub title: String,
+    pub body: String,
+    pub icon: Option<Icon>,
+    pub urgency: Urgency,
+    pub default_action: Option<Action>,
+    pub buttons: Vec<Button>,
+}
+
+impl TryFrom<&HashMap<String, OwnedValue>> for GNotification {
+    type Error = anyhow::Error;
+
+    fn try_from(notification: &HashMap<String, OwnedValue>) -> anyhow::Result<Self> {
+        let get = |key: &str| notification.get(key).map(|value| unwrap(value));
+
+        let string = |key: &str| -> anyhow::Result<Option<String>> {
+            match get(key) {
+                Some(Value::Str(s)) => Ok(Some(s.to_string())),
+                Some(value) => bail!("{} has the wrong type: {}", key, value.value_signature()),
+                None => Ok(None),
+            }
+        };
+
+        let urgency = match string("priority")?.as_deref() {
+            Some("low") => Urgency::Low,
+            Some("urgent") => Urgency::Critical,
+            // there is no urgency between normal and critical.
+            Some("normal") | Some("high") | None => Urgency::Normal,
+            Some(priority) => bail!("unknown priority: {}", priority),
+        };
+
+        let default_action = string("default-action")?.map(|name| Action {
+            name,
+            target: get("default-action-target").map(OwnedValue::from),
+        });
+
+        let buttons = match get("buttons") {
+            Some(Value::Array(buttons)) => buttons
+                .get()
+                .iter()
+                .map(button)
+                .collect::<anyhow::Result<_>>()?,
+            Some(value) => bail!("buttons has the wrong type: {}", value.value_signature()),
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            title: string("title")?.unwrap_or_default(),
+            body: string("body")?.unwrap_or_default(),
+            icon: get("icon").map(icon).transpose()?,
+            urgency,
+            default_action,
+            buttons,
+        })
+    }
+}
+
+/// Strips the variants that values may be wrapped in.
+fn unwrap<'a, 'v>(mut value: &'a Value<'v>) -> &'a Value<'v> {
+    while let Value::Value(inner) = value {
+        value = inner;
+    }
+    value
+}
+
+fn button(value: &Value) -> anyhow::Result<Button> {
+    let button: HashMap<String, OwnedValue> = match unwrap(value) {
+        Value::Dict(dict) => dict.clone().try_into()?,
+        value => bail!("button has the wrong type: {}", value.value_signature()),
+    };
+    let string = |key: &str| -> anyhow::Result<String> {
+        let value = button
+            .get(key)
+            .with_context(|| format!("button is missing {}", key))?;
+        match unwrap(value) {
+            Value::Str(s) => Ok(s.to_string()),
+            value => bail!("{} has the wrong type: {}", key, value.value_signature()),
+        }
+    };
+
+    Ok(Button {
+        label: string("label")?,
+        action: Action {
+            name: string("action")?,
+            target: button
+                .get("target")
+                .map(|value| OwnedValue::from(unwrap(value))),
+        },
+    })
+}
+
+/// Parses an icon that has been serialized with `g_icon_serialize`.
+fn icon(value: &Value) -> anyhow::Result<Icon> {
+    let fields = match value {
+        Value::Structure(s) if s.fields().len() == 2 => s.fields(),
+        value => bail!("icon has the wrong type: {}", value.value_signature()),
+    };
+
+    match (unwrap(&fields[0]), unwrap(&fields[1])) {
+        (Value::Str(kind), Value::Array(names)) if kind.as_str() == "themed" => names
+            .get()
+            .iter()
+            .map(|name| match unwrap(name) {
+                Value::Str(name) => Ok(name.to_string()),
+                _ => bail!("themed icon names must be strings"),
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Icon::Themed),
+        (Value::Str(kind), Value::Array(bytes)) if kind.as_str() == "bytes" => bytes
+            .get()
+            .iter()
+            .map(|byte| match byte {
+                Value::U8(byte) => Ok(*byte),
+                _ => bail!("icon bytes must be bytes"),
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Icon::Bytes),
+        (Value::Str(kind), Value::Str(path)) if kind.as_str() == "file" => {
+            Ok(Icon::File(path.to_string()))
+        }
+        (kind, _) => bail!("unsupported icon: {}", kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zvariant::{Array, Dict, Signature, StructureBuilder};
+
+    use super::*;
+
+    fn notification(entries: Vec<(&str, Value)>) -> HashMap<String, OwnedValue> {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), OwnedValue::from(value)))
+            .collect()
+    }
+
+    fn button(label: &str, action: &str, target: Option<Value<'static>>) -> Value<'static> {
+        let mut dict = Dict::new(
+            Signature::from_static_str_unchecked("s"),
+            Signature::from_static_str_unchecked("v"),
+        );
+        dict.add("label", Value::new(label.to_string())).unwrap();
+        dict.add("action", Value::new(action.to_string())).unwrap();
+        if let Some(target) = target {
+            dict.add("target", Value::new(target)).unwrap();
+        }
+        Value::Dict(dict)
+    }
+
+    fn icon(kind: &str, value: Value<'static>) -> Value<'static> {
+        Value::Structure(
+            StructureBuilder::new()
+                .add_field(kind.to_string())
+                .append_field(Value::new(value))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn test_demo() {
+        // what the demo application sends.
+        let mut buttons = Array::new(Signature::from_static_str_unchecked("a{sv}"));
+        buttons
+            .append(button("Yes", "app.activated", Some(Value::from("yes"))))
+            .unwrap();
+        buttons
+            .append(button("No", "app.activated", Some(Value::from("no"))))
+            .unwrap();
+
+        let parsed = GNotification::try_from(&notification(vec![
+            ("title", Value::from("Hello from Linux")),
+            (
+                "body",
+                Value::from("This notification was sent from Linux."),
+            ),
+            (
+                "icon",
+                icon(
+                    "themed",
+                    Value::from(vec!["security-high", "security-high-symbolic"]),
+                ),
+            ),
+            ("default-action", Value::from("app.activated")),
+            ("default-action-target", Value::from("default")),
+            ("buttons", Value::Array(buttons)),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            parsed,
+            GNotification {
+                title: String::from("Hello from Linux"),
+                body: String::from("This notification was sent from Linux."),
+                icon: Some(Icon::Themed(vec![
+                    String::from("security-high"),
+                    String::from("security-high-symbolic")
+                ])),
+                urgency: Urgency::Normal,
+                default_action: Some(Action {
+                    name: String::from("app.activated"),
+                    target: Some(OwnedValue::from(Value::from("default"))),
+                }),
+                buttons: vec![
+                    Button {
+                        label: String::from("Yes"),
+                        action: Action {
+                            name: String::from("app.activated"),
+                            target: Some(OwnedValue::from(Value::from("yes"))),
+                        },
+                    },
+                    Button {
+                        label: String::from("No"),
+                        action: Action {
+                            name: String::from("app.activated"),
+                            target: Some(OwnedValue::from(Value::from("no"))),
+                        },
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_minimal() {
+        let parsed =
+            GNotification::try_from(&notification(vec![("title", Value::from("Title"))])).unwrap();
+        assert_eq!(parsed.title, "Title");
+        assert_eq!(parsed.body, "");
+        assert_eq!(parsed.icon, None);
+        assert_eq!(parsed.default_action, None);
+        assert!(parsed.buttons.is_empty());
+    }
+
+    #[test]
+    fn test_icons() {
+        let parse = |value| {
+            GNotification::try_from(&notification(vec![("icon", value)])).map(|n| n.icon.unwrap())
+        };
+
+        assert_eq!(
+            parse(icon("bytes", Value::from(vec![0x89u8, b'P', b'N', b'G']))).unwrap(),
+            Icon::Bytes(vec![0x89, b'P', b'N', b'G'])
+        );
+        assert_eq!(
+            parse(icon("file", Value::from("/usr/share/pixmaps/app.png"))).unwrap(),
+            Icon::File(String::from("/usr/share/pixmaps/app.png"))
+        );
+        assert!(parse(icon("emblemed", Value::from("app"))).is_err());
+        assert!(parse(Value::from("security-high")).is_err());
+    }
+
+    #[test]
+    fn test_priority() {
+        let parse = |priority| {
+            GNotification::try_from(&notification(vec![("priority", Value::from(priority))]))
+                .map(|n| n.urgency)
+        };
+
+        assert_eq!(parse("low").unwrap(), Urgency::Low);
+        assert_eq!(parse("normal").unwrap(), Urgency::Normal);
+        assert_eq!(parse("high").unwrap(), Urgency::Normal);
+        assert_eq!(parse("urgent").unwrap(), Urgency::Critical);
+        assert!(parse("whenever").is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(
+            GNotification::try_from(&notification(vec![("title", Value::from(1u32))])).is_err()
+        );
+        assert!(
+            GNotification::try_from(&notification(vec![("buttons", Value::from("Yes"))])).is_err()
+        );
+
+        let mut buttons = Array::new(Signature::from_static_str_unchecked("a{sv}"));
+        let mut dict = Dict::new(
+            Signature::from_static_str_unchecked("s"),
+            Signature::from_static_str_unchecked("v"),
+        );
+        dict.add("label", Value::new("Yes")).unwrap();
+        buttons.append(Value::Dict(dict)).unwrap();
+        assert!(
+            GNotification::try_from(&notification(vec![("buttons", Value::Array(buttons))]))
+                .is_err()
+        );
+    }
+}
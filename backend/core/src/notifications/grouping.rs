@@ -0,0 +1,177 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Groups the toasts of an application by the category of the notifications, such that related
+//! notifications are collapsed under a header in the Action Center.
+
+use std::collections::BTreeMap;
+
+use sha2::{Digest, Sha256};
+
+/// The longest group that Windows accepts.
+const MAX_GROUP_LENGTH: usize = 64;
+
+/// Returns the categories that are grouped by default, and the titles of their headers.
+///
+/// The categories are either a full category such as `email.arrived`, or a class such as `email`,
+/// which matches every category of that class.
+pub fn default_category_groups() -> BTreeMap<String, String> {
+    [
+        ("device", "Devices"),
+        ("email", "Email"),
+        ("im", "Messages"),
+        ("network", "Network"),
+        ("presence", "Presence"),
+        ("transfer", "Transfers"),
+    ]
+    .iter()
+    .map(|(category, title)| (category.to_string(), title.to_string()))
+    .collect()
+}
+
+/// The group that the toast of a notification is shown in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    /// Identifies the group among the groups of every application.
+    pub id: String,
+    /// The title of the header that the toasts are collapsed under, if they are.
+    pub title: Option<String>,
+}
+
+impl Group {
+    /// Returns the group of a notification, where `app` is the desktop entry or the name of the
+    /// application that sent it.
+    pub fn new(
+        category_groups: &BTreeMap<String, String>,
+        app: &str,
+        app_name: &str,
+        category: Option<&str>,
+    ) -> Self {
+        // a full category takes precedence over its class.
+        let matched = category.and_then(|category| {
+            let class = category.split('.').next().unwrap_or(category);
+            category_groups
+                .get_key_value(category)
+                .or_else(|| category_groups.get_key_value(class))
+        });
+
+        match matched {
+            Some((category, title)) => Self {
+                id: id(&format!("{}/{}", app, category)),
+                title: Some(if app_name.is_empty() {
+                    title.clone()
+                } else {
+                    format!("{}: {}", app_name, title)
+                }),
+            },
+            None => Self::app(app),
+        }
+    }
+
+    /// Returns the group of the notifications of an application that aren't grouped further.
+    pub fn app(app: &str) -> Self {
+        Self {
+            id: id(app),
+            title: None,
+        }
+    }
+
+    /// Checks whether a toast in this group replaces the toast with the same tag in `previous`.
+    ///
+    /// Toasts are identified by both their tag and their group, so a toast that moves to another
+    /// group is shown next to the previous one, which has to be removed separately.
+    pub fn replaces(&self, previous: &Group) -> bool {
+        self.id == previous.id
+    }
+}
+
+/// Hashes groups that would be too long.
+fn id(group: &str) -> String {
+    if group.len() <= MAX_GROUP_LENGTH {
+        return group.to_string();
+    }
+
+    let hash = Sha256::new().chain_update(group).finalize();
+    format!("group-{:x}", hash)[..("group-".len() + 32)].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        let mut category_groups = default_category_groups();
+        category_groups.insert(String::from("email.bounced"), String::from("Bounces"));
+
+        assert_eq!(
+            Group::new(
+                &category_groups,
+                "org.gnome.Evolution",
+                "Evolution",
+                Some("email.arrived")
+            ),
+            Group {
+                id: String::from("org.gnome.Evolution/email"),
+                title: Some(String::from("Evolution: Email")),
+            }
+        );
+        assert_eq!(
+            Group::new(
+                &category_groups,
+                "org.gnome.Evolution",
+                "",
+                Some("email.bounced")
+            ),
+            Group {
+                id: String::from("org.gnome.Evolution/email.bounced"),
+                title: Some(String::from("Bounces")),
+            }
+        );
+
+        // notifications without a grouped category are only grouped by application.
+        let ungrouped = Group {
+            id: String::from("notify-send"),
+            title: None,
+        };
+        assert_eq!(
+            Group::new(&category_groups, "notify-send", "notify-send", None),
+            ungrouped
+        );
+        assert_eq!(
+            Group::new(
+                &category_groups,
+                "notify-send",
+                "notify-send",
+                Some("x-custom.thing")
+            ),
+            ungrouped
+        );
+    }
+
+    #[test]
+    fn test_replaces() {
+        let groups = default_category_groups();
+        let chat = |category| Group::new(&groups, "org.gnome.Chat", "Chat", category);
+
+        assert!(chat(Some("im.received")).replaces(&chat(Some("im"))));
+        assert!(chat(None).replaces(&chat(Some("x-custom.thing"))));
+        // the category of an update can change.
+        assert!(!chat(None).replaces(&chat(Some("im.received"))));
+        assert!(!chat(Some("presence.online")).replaces(&chat(Some("im.received"))));
+        assert!(!Group::app("org.gnome.Mail").replaces(&chat(None)));
+    }
+
+    #[test]
+    fn test_long_group() {
+        let app = "a".repeat(100);
+        let group = Group::new(&default_category_groups(), &app, "", Some("im.received"));
+        assert_eq!(group.id.len(), "group-".len() + 32);
+        assert_ne!(
+            group.id,
+            Group::new(&default_category_groups(), &app, "", None).id
+        );
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! A record of the notifications that have been sent, and what became of them.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::rules::Suppression;
+
+/// The default number of entries that are kept.
+pub const DEFAULT_MAX_ENTRIES: usize = 500;
+
+/// Returns the current time in milliseconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Activated,
+    Dismissed,
+    Expired,
+    /// Closed by the application, or by Windows.
+    Closed,
+    /// Replaced by a newer notification with the same id.
+    Replaced,
+    Failed,
+    Blocked,
+    DoNotDisturb,
+    Inhibited,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Activated => "activated",
+            Outcome::Dismissed => "dismissed",
+            Outcome::Expired => "expired",
+            Outcome::Closed => "closed",
+            Outcome::Replaced => "replaced",
+            Outcome::Failed => "failed",
+            Outcome::Blocked => "blocked",
+            Outcome::DoNotDisturb => "do-not-disturb",
+            Outcome::Inhibited => "inhibited",
+        }
+    }
+
+    /// Checks whether the notification was never shown.
+    pub fn is_suppressed(self) -> bool {
+        matches!(
+            self,
+            Outcome::Blocked | Outcome::DoNotDisturb | Outcome::Inhibited
+        )
+    }
+}
+
+impl From<Suppression> for Outcome {
+    fn from(suppression: Suppression) -> Self {
+        match suppression {
+            Suppression::Blocked => Outcome::Blocked,
+            Suppression::DoNotDisturb => Outcome::DoNotDisturb,
+            Suppression::Inhibited => Outcome::Inhibited,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Identifies the entry, since notification ids are reused.
+    pub seq: u64,
+    pub id: u32,
+    pub app_name: String,
+    pub summary: String,
+    pub body: String,
+    pub hints: BTreeMap<String, String>,
+    /// Milliseconds since the Unix epoch.
+    pub created: u64,
+    pub closed: Option<u64>,
+    /// Not set while the notification is still shown.
+    pub outcome: Option<Outcome>,
+}
+
+#[derive(Debug)]
+pub struct History {
+    max_entries: usize,
+    next_seq: u64,
+    /// The oldest entries come first.
+    entries: VecDeque<Entry>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl History {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            next_seq: 1,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Returns where the history is stored for the current user, if it is persisted.
+    pub fn default_path() -> Option<PathBuf> {
+        let mut path = PathBuf::from(std::env::var_os("LOCALAPPDATA")?);
+        path.push("Wormhole");
+        path.push("notification-history.json");
+        Some(path)
+    }
+
+    /// Loads the history from a file, or returns `None` if there is no such file.
+    pub fn load(path: &Path, max_entries: usize) -> anyhow::Result<Option<Self>> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // the notifications that were still shown went away with the previous instance, which
+        // was at the latest when the history was last saved.
+        let saved = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_millis() as u64);
+
+        let mut history = Self::new(max_entries);
+        let entries: Vec<Entry> = serde_json::from_slice(&data)?;
+        for mut entry in entries {
+            if entry.outcome.is_none() {
+                entry.outcome = Some(Outcome::Closed);
+                entry.closed = Some(saved.unwrap_or(entry.created).max(entry.created));
+            }
+            history.next_seq = history.next_seq.max(entry.seq + 1);
+            history.push(entry);
+        }
+
+        Ok(Some(history))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        Self::write(path, &self.to_json()?)
+    }
+
+    /// Serializes the history, such that it can be written without holding on to it.
+    pub fn to_json(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&self.entries)?)
+    }
+
+    /// Writes a history that was serialized with `to_json`.
+    pub fn write(path: &Path, json: &[u8]) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Adds an entry, giving it the next sequence number.
+    pub fn add(&mut self, mut entry: Entry) -> Entry {
+        entry.seq = self.next_seq;
+        self.next_seq += 1;
+        self.push(entry.clone());
+        entry
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries.push_back(entry);
+        while self.entries.len() > self.max_entries {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Records the outcome of a notification. Only the first outcome is kept.
+    pub fn close(&mut self, seq: u64, outcome: Outcome, now: u64) -> Option<&Entry> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.seq == seq)
+            .filter(|entry| entry.outcome.is_none())?;
+        entry.outcome = Some(outcome);
+        entry.closed = Some(now);
+        Some(entry)
+    }
+
+    /// Replaces the text and hints of a notification that is still shown, such as one whose
+    /// progress is updated in place.
+    pub fn update(
+        &mut self,
+        seq: u64,
+        summary: &str,
+        body: &str,
+        hints: BTreeMap<String, String>,
+    ) -> Option<&Entry> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.seq == seq)
+            .filter(|entry| entry.outcome.is_none())?;
+        entry.summary = summary.to_string();
+        entry.body = body.to_string();
+        entry.hints = hints;
+        Some(entry)
+    }
+
+    /// Returns the newest entries first, optionally only those of one application and those
+    /// created after a point in time.
+    pub fn query(&self, app_name: Option<&str>, since: u64, limit: usize) -> Vec<&Entry> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| app_name.is_none_or(|app_name| entry.app_name == app_name))
+            .filter(|entry| entry.created >= since)
+            .take(limit)
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u32, app_name: &str, created: u64) -> Entry {
+        Entry {
+            seq: 0,
+            id,
+            app_name: app_name.to_string(),
+            summary: format!("summary {}", id),
+            body: String::new(),
+            hints: BTreeMap::new(),
+            created,
+            closed: None,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut history = History::new(3);
+        for id in 1..=5 {
+            history.add(entry(id, "app", id as u64));
+        }
+
+        let ids: Vec<_> = history.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [3, 4, 5]);
+        let seqs: Vec<_> = history.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, [3, 4, 5]);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut history = History::new(0);
+        let seq = history.add(entry(1, "app", 100)).seq;
+
+        assert_eq!(history.iter().count(), 0);
+        assert!(history.close(seq, Outcome::Dismissed, 200).is_none());
+        // sequence numbers are still unique.
+        assert_eq!(history.add(entry(2, "app", 200)).seq, seq + 1);
+    }
+
+    #[test]
+    fn test_close() {
+        let mut history = History::new(10);
+        let first = history.add(entry(1, "app", 100)).seq;
+        // ids are reused when a notification replaces another.
+        let second = history.add(entry(1, "app", 200)).seq;
+
+        let closed = history.close(first, Outcome::Replaced, 200).unwrap();
+        assert_eq!(closed.outcome, Some(Outcome::Replaced));
+        assert_eq!(closed.closed, Some(200));
+
+        assert!(history.close(second, Outcome::Activated, 300).is_some());
+        // the outcome that came first is kept.
+        assert!(history.close(second, Outcome::Dismissed, 400).is_none());
+        assert!(history.close(42, Outcome::Dismissed, 400).is_none());
+
+        let outcomes: Vec<_> = history.iter().map(|entry| entry.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [Some(Outcome::Replaced), Some(Outcome::Activated)]
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let mut history = History::new(10);
+        let seq = history.add(entry(1, "app", 100)).seq;
+
+        let hints: BTreeMap<_, _> = vec![(String::from("value"), String::from("50"))]
+            .into_iter()
+            .collect();
+        let updated = history
+            .update(seq, "Copying", "2 of 4 files", hints.clone())
+            .unwrap();
+        assert_eq!(
+            (updated.summary.as_str(), updated.body.as_str()),
+            ("Copying", "2 of 4 files")
+        );
+        assert_eq!(updated.hints, hints);
+        // the entry is updated rather than added.
+        assert_eq!(history.iter().count(), 1);
+        assert_eq!(history.iter().next().unwrap().seq, seq);
+
+        // closed notifications are no longer updated.
+        history.close(seq, Outcome::Dismissed, 200);
+        assert!(history.update(seq, "Copied", "", BTreeMap::new()).is_none());
+        assert!(history.update(42, "Copied", "", BTreeMap::new()).is_none());
+        assert_eq!(history.iter().next().unwrap().summary, "Copying");
+    }
+
+    #[test]
+    fn test_query() {
+        let mut history = History::new(10);
+        history.add(entry(1, "alerts", 100));
+        history.add(entry(2, "mail", 200));
+        history.add(entry(3, "alerts", 300));
+        history.add(entry(4, "alerts", 400));
+
+        let ids = |entries: Vec<&Entry>| entries.iter().map(|entry| entry.id).collect::<Vec<_>>();
+        assert_eq!(ids(history.query(None, 0, usize::MAX)), [4, 3, 2, 1]);
+        assert_eq!(ids(history.query(Some("alerts"), 0, 2)), [4, 3]);
+        assert_eq!(ids(history.query(Some("alerts"), 150, usize::MAX)), [4, 3]);
+        assert!(history.query(Some("chat"), 0, usize::MAX).is_empty());
+
+        history.clear();
+        assert!(history.query(None, 0, usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Wormhole").join("history.json");
+
+        assert!(History::load(&path, 10).unwrap().is_none());
+
+        let mut history = History::new(10);
+        let first = history.add(entry(1, "alerts", 100)).seq;
+        let seq = history.add(entry(2, "alerts", 200)).seq;
+        history.close(first, Outcome::Dismissed, 150);
+        history.close(seq, Outcome::Expired, 300);
+        history.save(&path).unwrap();
+
+        let mut loaded = History::load(&path, 10).unwrap().unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            history.iter().collect::<Vec<_>>()
+        );
+        // sequence numbers continue where they left off.
+        assert_eq!(loaded.add(entry(3, "alerts", 400)).seq, 3);
+
+        // a smaller limit drops the oldest entries.
+        let loaded = History::load(&path, 1).unwrap().unwrap();
+        assert_eq!(loaded.iter().map(|entry| entry.id).collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn test_load_closes_shown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+
+        let mut history = History::new(10);
+        let seq = history.add(entry(1, "alerts", 100)).seq;
+        history.add(entry(2, "alerts", 200));
+        history.close(seq, Outcome::Activated, 150);
+        history.save(&path).unwrap();
+
+        let mut loaded = History::load(&path, 10).unwrap().unwrap();
+        let outcomes: Vec<_> = loaded.iter().map(|entry| entry.outcome).collect();
+        assert_eq!(outcomes, [Some(Outcome::Activated), Some(Outcome::Closed)]);
+        let entry = loaded.iter().last().unwrap();
+        assert!(entry.closed.unwrap() >= entry.created);
+        // nothing can close them later.
+        assert!(loaded.close(entry.seq, Outcome::Dismissed, 300).is_none());
+    }
+
+    #[test]
+    fn test_suppressed() {
+        assert!(Outcome::from(Suppression::DoNotDisturb).is_suppressed());
+        assert!(!Outcome::Dismissed.is_suppressed());
+        assert_eq!(
+            serde_json::to_string(&Outcome::DoNotDisturb).unwrap(),
+            format!("\"{}\"", Outcome::DoNotDisturb.as_str())
+        );
+    }
+}
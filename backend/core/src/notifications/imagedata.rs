@@ -0,0 +1,328 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Decodes the raw images that are sent in the `image-data` hint.
+//!
+//! The format is the one used by GdkPixbuf: rows of `width * channels` bytes, each starting
+//! `rowstride` bytes after the previous one. The padding after the last row is usually left out.
+
+use std::convert::TryFrom;
+
+use anyhow::{bail, Context};
+use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use zvariant::{OwnedValue, Value};
+use zvariant_derive::Type;
+
+/// Images larger than this in either direction are rejected.
+const MAX_DIMENSION: i32 = 4096;
+
+#[derive(Clone, Debug, Type, Serialize, Deserialize, Value, OwnedValue)]
+pub struct Image {
+    pub width: i32,
+    pub height: i32,
+    pub rowstride: i32,
+    pub has_alpha: bool,
+    pub bits_per_sample: i32,
+    pub channels: i32,
+    pub data: Vec<u8>,
+}
+
+pub fn decode(image: &Image) -> anyhow::Result<DynamicImage> {
+    if image.width <= 0 || image.height <= 0 {
+        bail!("invalid image size {}x{}", image.width, image.height);
+    }
+    if image.width > MAX_DIMENSION || image.height > MAX_DIMENSION {
+        bail!("image is too large ({}x{})", image.width, image.height);
+    }
+    if image.bits_per_sample != 8 {
+        bail!("unsupported bits per sample: {}", image.bits_per_sample);
+    }
+    if !(1..=4).contains(&image.channels) {
+        bail!("unsupported number of channels: {}", image.channels);
+    }
+    // the alpha channel is the last one, so the two must agree.
+    if image.has_alpha != (image.channels == 2 || image.channels == 4) {
+        bail!(
+            "has_alpha is {} for an image with {} channels",
+            image.has_alpha,
+            image.channels
+        );
+    }
+
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let row_len = width * image.channels as usize;
+    let rowstride = usize::try_from(image.rowstride).context("negative rowstride")?;
+    if rowstride < row_len {
+        bail!(
+            "rowstride {} is shorter than a row ({})",
+            rowstride,
+            row_len
+        );
+    }
+
+    // the last row doesn't have to be padded.
+    let min_len = rowstride
+        .checked_mul(height - 1)
+        .and_then(|len| len.checked_add(row_len))
+        .context("rowstride is too large")?;
+    if image.data.len() < min_len {
+        bail!(
+            "image data is too short ({} bytes, expected at least {})",
+            image.data.len(),
+            min_len
+        );
+    }
+
+    let mut pixels = Vec::with_capacity(row_len * height);
+    for row in 0..height {
+        let start = row * rowstride;
+        pixels.extend_from_slice(&image.data[start..start + row_len]);
+    }
+
+    let (width, height) = (width as u32, height as u32);
+    // the buffers have exactly the right size, so this can't fail.
+    let decoded = match image.channels {
+        1 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        2 => GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        3 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        _ => RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+    };
+
+    decoded.context("image buffer has the wrong size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::LE;
+    use image::GenericImageView;
+    use sha2::{Digest, Sha256};
+    use zvariant::EncodingContext;
+
+    fn image(width: i32, height: i32, rowstride: i32, channels: i32, data: Vec<u8>) -> Image {
+        Image {
+            width,
+            height,
+            rowstride,
+            has_alpha: channels == 2 || channels == 4,
+            bits_per_sample: 8,
+            channels,
+            data,
+        }
+    }
+
+    /// Lays out pixels the way GdkPixbuf does, with rows aligned to 4 bytes and no padding after
+    /// the last row.
+    fn pixbuf(
+        width: i32,
+        height: i32,
+        channels: i32,
+        pixel: impl Fn(i32, i32) -> Vec<u8>,
+    ) -> Image {
+        let rowstride = (width * channels + 3) & !3;
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                data.extend(pixel(x, y));
+            }
+            if y != height - 1 {
+                data.resize((rowstride * (y + 1)) as usize, 0xAA);
+            }
+        }
+        image(width, height, rowstride, channels, data)
+    }
+
+    /// A 3x2 RGBA image.
+    fn rgba() -> Image {
+        pixbuf(3, 2, 4, |x, y| vec![x as u8, y as u8, 200, 255])
+    }
+
+    /// Decodes an `image-data` payload, as it was sent over D-Bus.
+    fn payload(bytes: &[u8]) -> Image {
+        zvariant::from_slice(bytes, EncodingContext::<LE>::new_dbus(0)).unwrap()
+    }
+
+    /// Returns the size and a hash of the pixels, without any padding.
+    fn pixels(image: &Image) -> ((u32, u32), String) {
+        let decoded = decode(image).unwrap();
+        let hash = Sha256::digest(decoded.as_bytes());
+        let hash = hash.iter().map(|b| format!("{:02x}", b)).collect();
+        (decoded.dimensions(), hash)
+    }
+
+    #[test]
+    fn test_gdkpixbuf_payloads() {
+        // RGB with an odd width, where every row but the last is padded.
+        let image = payload(include_bytes!("testdata/gdkpixbuf-rgb-13x11.bin"));
+        assert_eq!((image.rowstride, image.data.len()), (40, 439));
+        assert_eq!(
+            pixels(&image),
+            (
+                (13, 11),
+                "63892017a2b31eede15b20277818ce69f8aa2dde9484e46bd8b894459e94381b".into()
+            )
+        );
+
+        let image = payload(include_bytes!("testdata/gdkpixbuf-rgba-16x16.bin"));
+        assert!(image.has_alpha);
+        assert_eq!(
+            pixels(&image),
+            (
+                (16, 16),
+                "ffc9d48c20f9a97c292c71aca3ae567de1b129dd5bab724cf4e58496f193abbe".into()
+            )
+        );
+
+        // the rows of a sub-pixbuf are as far apart as those of its parent.
+        let image = payload(include_bytes!("testdata/gdkpixbuf-subpixbuf-5x7.bin"));
+        assert_eq!((image.rowstride, image.data.len()), (216, 1311));
+        assert_eq!(
+            pixels(&image),
+            (
+                (5, 7),
+                "11260c1a5c41d6979e2f752d1166c6c707f0b00b0260802a8fbd14571e2c0957".into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_qt_payloads() {
+        // the whole QImage is sent, including the padding after the last row.
+        let image = payload(include_bytes!("testdata/qt-rgb888-13x11.bin"));
+        assert_eq!((image.rowstride, image.data.len()), (40, 440));
+        assert_eq!(
+            pixels(&image),
+            pixels(&payload(include_bytes!("testdata/gdkpixbuf-rgb-13x11.bin")))
+        );
+
+        let image = payload(include_bytes!("testdata/qt-rgba8888-16x16.bin"));
+        assert_eq!(
+            pixels(&image),
+            pixels(&payload(include_bytes!(
+                "testdata/gdkpixbuf-rgba-16x16.bin"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_electron_payload() {
+        let image = payload(include_bytes!("testdata/electron-rgba-24x24.bin"));
+        assert_eq!(
+            pixels(&image),
+            (
+                (24, 24),
+                "532649168337ced94ebf2febd7c4fda7256fda333d718db183179c6c45fd025e".into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_gray() {
+        let image = pixbuf(3, 3, 1, |x, y| vec![(x + y) as u8]);
+        let decoded = decode(&image).unwrap();
+        assert_eq!(decoded.get_pixel(2, 1).0, [3, 3, 3, 255]);
+
+        let image = pixbuf(3, 3, 2, |x, _| vec![x as u8, 128]);
+        let decoded = decode(&image).unwrap();
+        assert_eq!(decoded.get_pixel(2, 0).0, [2, 2, 2, 128]);
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        assert!(decode(&rgba()).is_ok());
+
+        let mut image = rgba();
+        image.width = 0;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "invalid image size 0x2"
+        );
+
+        let mut image = rgba();
+        image.height = -2;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "invalid image size 3x-2"
+        );
+
+        let mut image = rgba();
+        image.width = i32::MAX;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "image is too large (2147483647x2)"
+        );
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        let mut image = rgba();
+        image.bits_per_sample = 16;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "unsupported bits per sample: 16"
+        );
+
+        let mut image = rgba();
+        image.channels = 0;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "unsupported number of channels: 0"
+        );
+
+        let mut image = rgba();
+        image.channels = 5;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "unsupported number of channels: 5"
+        );
+
+        let mut image = rgba();
+        image.has_alpha = false;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "has_alpha is false for an image with 4 channels"
+        );
+    }
+
+    #[test]
+    fn test_invalid_layout() {
+        let mut image = rgba();
+        image.rowstride = -12;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "negative rowstride"
+        );
+
+        let mut image = rgba();
+        image.rowstride = 11;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "rowstride 11 is shorter than a row (12)"
+        );
+
+        let mut image = rgba();
+        image.rowstride = i32::MAX;
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "image data is too short (24 bytes, expected at least 2147483659)"
+        );
+
+        let mut image = rgba();
+        image.data.pop();
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "image data is too short (23 bytes, expected at least 24)"
+        );
+
+        let mut image = rgba();
+        image.data.clear();
+        assert_eq!(
+            decode(&image).unwrap_err().to_string(),
+            "image data is too short (0 bytes, expected at least 24)"
+        );
+    }
+}
@@ -0,0 +1,465 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Parser for the markup that is allowed in notification bodies.
+//!
+//! The spec allows `<b>`, `<i>`, `<u>`, `<a href="...">` and `<img src="..." alt="..."/>`, and
+//! entities for escaping. Toasts can't show formatted text, so the text is extracted, links are
+//! collected so that they can be shown as buttons, and the first image can be shown as a hero image.
+//! A body that isn't valid markup is shown as it is.
+
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Deeper nesting than this is treated as malformed.
+const MAX_DEPTH: usize = 32;
+
+/// URL schemes that are safe to hand over to Windows.
+const LINK_SCHEMES: &[&str] = &["http://", "https://", "mailto:"];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Body {
+    pub text: String,
+    pub links: Vec<Link>,
+    /// The path of the first image, if it refers to a local file.
+    pub image: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Link {
+    pub text: String,
+    pub href: String,
+}
+
+/// Parses a notification body, falling back to the body as plain text if it's malformed.
+pub fn parse(body: &str) -> Body {
+    Parser::new(body).parse().unwrap_or_else(|| Body {
+        text: body.to_string(),
+        ..Default::default()
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tag {
+    Bold,
+    Italic,
+    Underline,
+    Link,
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    body: Body,
+    open: Vec<Tag>,
+    /// The link that is currently open, and where its text starts.
+    link: Option<(Option<String>, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            chars: input.char_indices().peekable(),
+            body: Body::default(),
+            open: Vec::new(),
+            link: None,
+        }
+    }
+
+    fn parse(mut self) -> Option<Body> {
+        while let Some((_, c)) = self.chars.next() {
+            match c {
+                '<' => self.tag()?,
+                '&' => {
+                    let c = self.entity()?;
+                    self.body.text.push(c);
+                }
+                '>' => return None,
+                c => self.body.text.push(c),
+            }
+        }
+
+        if !self.open.is_empty() {
+            return None;
+        }
+
+        Some(self.body)
+    }
+
+    fn tag(&mut self) -> Option<()> {
+        let closing = self.chars.next_if(|&(_, c)| c == '/').is_some();
+        let name = self.name()?;
+
+        if closing {
+            self.skip_whitespace();
+            self.expect('>')?;
+            return self.close(&name);
+        }
+
+        let (attributes, self_closing) = self.attributes()?;
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        let tag = match name.as_str() {
+            "b" => Tag::Bold,
+            "i" => Tag::Italic,
+            "u" => Tag::Underline,
+            "a" => Tag::Link,
+            // images never have any content, so they are allowed to be left open.
+            "img" => {
+                self.image(attribute("src"), attribute("alt"));
+                return Some(());
+            }
+            _ => return None,
+        };
+
+        if self_closing {
+            return Some(());
+        }
+        if self.open.len() >= MAX_DEPTH {
+            return None;
+        }
+
+        if tag == Tag::Link {
+            // links can't be nested.
+            if self.link.is_some() {
+                return None;
+            }
+            self.link = Some((attribute("href"), self.body.text.len()));
+        }
+
+        self.open.push(tag);
+        Some(())
+    }
+
+    fn close(&mut self, name: &str) -> Option<()> {
+        let tag = match name {
+            "b" => Tag::Bold,
+            "i" => Tag::Italic,
+            "u" => Tag::Underline,
+            "a" => Tag::Link,
+            "img" => return Some(()),
+            _ => return None,
+        };
+
+        if self.open.pop() != Some(tag) {
+            return None;
+        }
+
+        if tag == Tag::Link {
+            let (href, start) = self.link.take()?;
+            if let Some(href) = href.filter(|href| is_safe_link(href)) {
+                let text = self.body.text[start..].trim();
+                self.body.links.push(Link {
+                    text: if text.is_empty() {
+                        href.clone()
+                    } else {
+                        text.to_string()
+                    },
+                    href,
+                });
+            }
+        }
+
+        Some(())
+    }
+
+    fn image(&mut self, src: Option<String>, alt: Option<String>) {
+        if self.body.image.is_none() {
+            if let Some(path) = src.as_deref().and_then(local_path) {
+                self.body.image = Some(path);
+                return;
+            }
+        }
+
+        // show the alternative text for images that can't be shown.
+        if let Some(alt) = alt {
+            self.body.text.push_str(&alt);
+        }
+    }
+
+    /// Reads the attributes of a tag, up to and including the closing `>`.
+    fn attributes(&mut self) -> Option<(Vec<(String, String)>, bool)> {
+        let mut attributes = Vec::new();
+
+        loop {
+            let had_whitespace = self.skip_whitespace();
+
+            match self.chars.next()?.1 {
+                '>' => return Some((attributes, false)),
+                '/' => {
+                    self.expect('>')?;
+                    return Some((attributes, true));
+                }
+                c if had_whitespace && is_name_char(c) => {
+                    let mut key = String::from(c);
+                    while let Some((_, c)) = self.chars.next_if(|&(_, c)| is_name_char(c)) {
+                        key.push(c);
+                    }
+
+                    self.skip_whitespace();
+                    self.expect('=')?;
+                    self.skip_whitespace();
+                    let value = self.value()?;
+
+                    attributes.push((key.to_ascii_lowercase(), value));
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn value(&mut self) -> Option<String> {
+        let quote = self.chars.next()?.1;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+
+        let mut value = String::new();
+        loop {
+            match self.chars.next()?.1 {
+                c if c == quote => return Some(value),
+                '&' => value.push(self.entity()?),
+                '<' => return None,
+                c => value.push(c),
+            }
+        }
+    }
+
+    /// Decodes an entity, after the `&`.
+    fn entity(&mut self) -> Option<char> {
+        let (start, _) = *self.chars.peek()?;
+        let end = loop {
+            match self.chars.next()? {
+                (i, ';') => break i,
+                (_, c) if c.is_ascii_alphanumeric() || c == '#' => {}
+                _ => return None,
+            }
+        };
+
+        match &self.input[start..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => {
+                let code = if let Some(hex) = entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                {
+                    u32::from_str_radix(hex, 16).ok()?
+                } else {
+                    entity.strip_prefix('#')?.parse().ok()?
+                };
+                char::from_u32(code).filter(|c| *c != '\0')
+            }
+        }
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let mut name = String::new();
+        while let Some((_, c)) = self.chars.next_if(|&(_, c)| is_name_char(c)) {
+            name.push(c.to_ascii_lowercase());
+        }
+        if name.is_empty() {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let mut skipped = false;
+        while self.chars.next_if(|&(_, c)| c.is_whitespace()).is_some() {
+            skipped = true;
+        }
+        skipped
+    }
+
+    fn expect(&mut self, expected: char) -> Option<()> {
+        self.chars.next_if(|&(_, c)| c == expected).map(|_| ())
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+fn is_safe_link(href: &str) -> bool {
+    let href = href.trim();
+    LINK_SCHEMES.iter().any(|scheme| {
+        href.len() > scheme.len()
+            && href[..scheme.len()].eq_ignore_ascii_case(scheme)
+            && !href.chars().any(char::is_control)
+    })
+}
+
+/// Returns the path of an image that refers to a local file.
+fn local_path(src: &str) -> Option<String> {
+    let path = match src.strip_prefix("file://") {
+        Some(path) => percent_decode(path)?,
+        None => src.to_string(),
+    };
+
+    if path.starts_with('/') && !path.chars().any(char::is_control) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> Body {
+        Body {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(parse("hello world"), plain("hello world"));
+        assert_eq!(parse(""), plain(""));
+        assert_eq!(parse("line 1\nline 2"), plain("line 1\nline 2"));
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(
+            parse("<b>bold</b>, <i>italic</i> and <u><B>both</B></u>"),
+            plain("bold, italic and both")
+        );
+    }
+
+    #[test]
+    fn test_entities() {
+        assert_eq!(
+            parse("&lt;b&gt; &amp; &quot;&apos; &#65;&#x42;"),
+            plain("<b> & \"' AB")
+        );
+    }
+
+    #[test]
+    fn test_links() {
+        assert_eq!(
+            parse(r#"see <a href="https://example.com/?a=1&amp;b=2">the docs</a>"#),
+            Body {
+                text: String::from("see the docs"),
+                links: vec![Link {
+                    text: String::from("the docs"),
+                    href: String::from("https://example.com/?a=1&b=2"),
+                }],
+                image: None,
+            }
+        );
+
+        assert_eq!(
+            parse("<a href='mailto:me@example.com'></a>").links,
+            [Link {
+                text: String::from("mailto:me@example.com"),
+                href: String::from("mailto:me@example.com"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unsafe_links() {
+        for href in [
+            "javascript:alert(1)",
+            "file:///etc/passwd",
+            "ms-settings:",
+            "C:\\Windows\\System32\\calc.exe",
+            "http://",
+            "https://example.com/\u{7}",
+        ] {
+            let body = parse(&format!(r#"<a href="{}">click</a>"#, href));
+            assert_eq!(body, plain("click"), "{}", href);
+        }
+
+        assert_eq!(parse("<a>no link</a>"), plain("no link"));
+    }
+
+    #[test]
+    fn test_images() {
+        assert_eq!(
+            parse(
+                r#"<img src="file:///tmp/a%20b.png" alt="first"/><img src="/tmp/c.png" alt="second">"#
+            ),
+            Body {
+                text: String::from("second"),
+                links: Vec::new(),
+                image: Some(String::from("/tmp/a b.png")),
+            }
+        );
+        assert_eq!(
+            parse(r#"<img src="https://example.com/tracker.png" alt="remote" />"#),
+            plain("remote")
+        );
+        assert_eq!(parse("<img/>"), plain(""));
+    }
+
+    #[test]
+    fn test_malformed() {
+        for body in [
+            "Tom & Jerry",
+            "1 < 2",
+            "2 > 1",
+            "<b>unclosed",
+            "closed</b>",
+            "<b><i>crossed</b></i>",
+            "<script>alert(1)</script>",
+            "<b",
+            "<b attr>",
+            "<a href=unquoted>x</a>",
+            r#"<a href="x"href="y">x</a>"#,
+            r#"<a href="<b>">x</a>"#,
+            "<a href='https://a'><a href='https://b'>nested</a></a>",
+            "&bogus;",
+            "&#0;",
+            "&#x110000;",
+            "&#99999999999999999999;",
+            "&amp",
+            "<!-- comment -->",
+            "<![CDATA[x]]>",
+            "<?xml version='1.0'?>",
+            "</>",
+            "<>",
+        ] {
+            assert_eq!(parse(body), plain(body), "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let body = "<b>".repeat(10_000) + &"</b>".repeat(10_000);
+        assert_eq!(parse(&body), plain(&body));
+
+        let body = "<b>".repeat(MAX_DEPTH) + "ok" + &"</b>".repeat(MAX_DEPTH);
+        assert_eq!(parse(&body), plain("ok"));
+    }
+}
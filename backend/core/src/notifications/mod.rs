@@ -0,0 +1,18 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! How notifications are shown, independent of the Windows APIs that show them.
+
+pub mod appid;
+pub mod gnotification;
+pub mod grouping;
+pub mod history;
+pub mod imagedata;
+pub mod markup;
+pub mod progress;
+pub mod rules;
+pub mod sound;
+pub mod timing;
+pub mod toast;
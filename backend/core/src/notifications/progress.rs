@@ -0,0 +1,88 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Progress bars for notifications with a `value` hint.
+//!
+//! The text and the progress bar of the toast are bound to data values, so that updates can be
+//! pushed to a toast that is already shown, without showing it again.
+
+use super::toast::ProgressBar;
+
+/// Returns the title and the progress bar of a toast, bound to the values from [`Progress::values`].
+pub fn bound() -> (String, ProgressBar) {
+    (
+        String::from("{summary}"),
+        ProgressBar {
+            value: String::from("{progressValue}"),
+            value_string_override: Some(String::from("{progressValueString}")),
+            status: String::from("{progressStatus}"),
+        },
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Percent, between 0 and 100.
+    value: u32,
+}
+
+impl Progress {
+    pub fn new(value: i32) -> Self {
+        Self {
+            value: value.clamp(0, 100) as u32,
+        }
+    }
+
+    /// Returns the values that are bound to the placeholders from [`bound`].
+    pub fn values(&self, summary: &str, body: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("summary", summary.to_string()),
+            ("progressValue", format!("{}", self.value as f64 / 100.0)),
+            ("progressValueString", format!("{}%", self.value)),
+            ("progressStatus", body.to_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        assert_eq!(
+            Progress::new(42).values("Copying files", "3 of 7"),
+            [
+                ("summary", String::from("Copying files")),
+                ("progressValue", String::from("0.42")),
+                ("progressValueString", String::from("42%")),
+                ("progressStatus", String::from("3 of 7")),
+            ]
+        );
+        assert_eq!(Progress::new(100).values("", "")[1].1, "1");
+        assert_eq!(Progress::new(0).values("", "")[1].1, "0");
+    }
+
+    #[test]
+    fn test_clamped() {
+        assert_eq!(Progress::new(-5), Progress::new(0));
+        assert_eq!(Progress::new(250), Progress::new(100));
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let (title, bar) = bound();
+        let placeholders = [
+            Some(title),
+            Some(bar.value),
+            bar.value_string_override,
+            Some(bar.status),
+        ];
+        for (key, _) in Progress::new(50).values("", "") {
+            let placeholder = format!("{{{}}}", key);
+            assert!(placeholders.contains(&Some(placeholder)), "{}", key);
+        }
+    }
+}
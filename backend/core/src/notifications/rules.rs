@@ -0,0 +1,364 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Decides whether notifications are shown, based on the rules for the application that sent them,
+//! do not disturb, and the applications that inhibit notifications.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use super::{grouping, timing::Urgency};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rule {
+    Allow,
+    /// Shown without a sound.
+    Silent,
+    Block,
+}
+
+impl Rule {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rule::Allow => "allow",
+            Rule::Silent => "silent",
+            Rule::Block => "block",
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "allow" => Ok(Rule::Allow),
+            "silent" => Ok(Rule::Silent),
+            "block" => Ok(Rule::Block),
+            _ => bail!("unknown rule: {}", s),
+        }
+    }
+}
+
+/// Why a notification was not shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suppression {
+    Blocked,
+    DoNotDisturb,
+    Inhibited,
+}
+
+impl Suppression {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Suppression::Blocked => "blocked",
+            Suppression::DoNotDisturb => "do-not-disturb",
+            Suppression::Inhibited => "inhibited",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Show,
+    ShowSilently,
+    Suppress(Suppression),
+}
+
+/// The settings that decide which notifications are shown, and how they are grouped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Rules {
+    pub do_not_disturb: bool,
+    /// Rules keyed on either the desktop entry or the name of an application.
+    pub apps: BTreeMap<String, Rule>,
+    /// The titles of the headers that notifications are grouped under, by category.
+    pub category_groups: BTreeMap<String, String>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            do_not_disturb: false,
+            apps: BTreeMap::new(),
+            category_groups: grouping::default_category_groups(),
+        }
+    }
+}
+
+impl Rules {
+    /// Returns where the rules are stored for the current user.
+    pub fn default_path() -> Option<PathBuf> {
+        let mut path = PathBuf::from(std::env::var_os("APPDATA")?);
+        path.push("Wormhole");
+        path.push("notification-rules.json");
+        Some(path)
+    }
+
+    /// Loads the rules from a file, or returns the default rules if there is no such file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn set(&mut self, app: &str, rule: Rule) {
+        // allowing is the default, so there is no need to remember it.
+        if rule == Rule::Allow {
+            self.apps.remove(app);
+        } else {
+            self.apps.insert(app.to_string(), rule);
+        }
+    }
+
+    /// Sets the title of the header that notifications of a category are grouped under. An empty
+    /// title stops grouping them.
+    pub fn set_category_group(&mut self, category: &str, title: &str) {
+        if title.is_empty() {
+            self.category_groups.remove(category);
+        } else {
+            self.category_groups
+                .insert(category.to_string(), title.to_string());
+        }
+    }
+
+    /// Returns the rule for an application, preferring the one for its desktop entry.
+    pub fn rule_for(&self, app_name: &str, desktop_entry: Option<&str>) -> Rule {
+        desktop_entry
+            .and_then(|entry| self.apps.get(entry))
+            .or_else(|| self.apps.get(app_name))
+            .copied()
+            .unwrap_or(Rule::Allow)
+    }
+
+    pub fn decide(
+        &self,
+        app_name: &str,
+        desktop_entry: Option<&str>,
+        urgency: Urgency,
+        inhibited: bool,
+    ) -> Decision {
+        let rule = self.rule_for(app_name, desktop_entry);
+        if rule == Rule::Block {
+            return Decision::Suppress(Suppression::Blocked);
+        }
+
+        // critical notifications get through, as they do on GNOME and KDE.
+        if urgency != Urgency::Critical {
+            if self.do_not_disturb {
+                return Decision::Suppress(Suppression::DoNotDisturb);
+            }
+            if inhibited {
+                return Decision::Suppress(Suppression::Inhibited);
+            }
+        }
+
+        if rule == Rule::Silent {
+            Decision::ShowSilently
+        } else {
+            Decision::Show
+        }
+    }
+}
+
+/// Applications that have asked for notifications to be inhibited, such as while presenting.
+#[derive(Debug, Default)]
+pub struct Inhibitions {
+    next_cookie: u32,
+    /// The unique bus name of the application that holds each inhibition, such that it can be
+    /// released if the application goes away without releasing it.
+    active: BTreeMap<u32, Option<String>>,
+}
+
+impl Inhibitions {
+    /// Adds an inhibition and returns the cookie that releases it.
+    pub fn add(&mut self, owner: Option<String>) -> u32 {
+        // zero is never a valid cookie.
+        loop {
+            self.next_cookie = self.next_cookie.checked_add(1).unwrap_or(1);
+            if !self.active.contains_key(&self.next_cookie) {
+                break;
+            }
+        }
+        self.active.insert(self.next_cookie, owner);
+        self.next_cookie
+    }
+
+    /// Releases an inhibition, and returns false if there was no such inhibition.
+    pub fn remove(&mut self, cookie: u32) -> bool {
+        self.active.remove(&cookie).is_some()
+    }
+
+    /// Releases the inhibitions of an application, and returns false if it had none.
+    pub fn remove_owner(&mut self, owner: &str) -> bool {
+        let len = self.active.len();
+        self.active
+            .retain(|_, inhibitor| inhibitor.as_deref() != Some(owner));
+        self.active.len() != len
+    }
+
+    pub fn is_inhibited(&self) -> bool {
+        !self.active.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(do_not_disturb: bool, apps: &[(&str, Rule)]) -> Rules {
+        Rules {
+            do_not_disturb,
+            apps: apps
+                .iter()
+                .map(|(app, rule)| (app.to_string(), *rule))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_app_rules() {
+        let rules = rules(
+            false,
+            &[
+                ("Spotify", Rule::Silent),
+                ("org.gnome.Evolution", Rule::Block),
+                ("evolution", Rule::Allow),
+            ],
+        );
+
+        assert_eq!(
+            rules.decide("Spotify", None, Urgency::Normal, false),
+            Decision::ShowSilently
+        );
+        assert_eq!(
+            rules.decide("Firefox", None, Urgency::Normal, false),
+            Decision::Show
+        );
+        // the desktop entry is more specific than the name.
+        assert_eq!(
+            rules.decide(
+                "evolution",
+                Some("org.gnome.Evolution"),
+                Urgency::Normal,
+                false
+            ),
+            Decision::Suppress(Suppression::Blocked)
+        );
+        assert_eq!(
+            rules.decide("Spotify", Some("spotify"), Urgency::Normal, false),
+            Decision::ShowSilently
+        );
+    }
+
+    #[test]
+    fn test_do_not_disturb() {
+        let rules = rules(true, &[("Spotify", Rule::Silent), ("Steam", Rule::Block)]);
+
+        assert_eq!(
+            rules.decide("Firefox", None, Urgency::Normal, false),
+            Decision::Suppress(Suppression::DoNotDisturb)
+        );
+        assert_eq!(
+            rules.decide("Firefox", None, Urgency::Low, true),
+            Decision::Suppress(Suppression::DoNotDisturb)
+        );
+        assert_eq!(
+            rules.decide("Spotify", None, Urgency::Critical, false),
+            Decision::ShowSilently
+        );
+        // blocking applies to critical notifications as well.
+        assert_eq!(
+            rules.decide("Steam", None, Urgency::Critical, false),
+            Decision::Suppress(Suppression::Blocked)
+        );
+    }
+
+    #[test]
+    fn test_inhibited() {
+        let rules = Rules::default();
+
+        assert_eq!(
+            rules.decide("Firefox", None, Urgency::Normal, true),
+            Decision::Suppress(Suppression::Inhibited)
+        );
+        assert_eq!(
+            rules.decide("Firefox", None, Urgency::Critical, true),
+            Decision::Show
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        let mut rules = Rules::default();
+        rules.set("Spotify", Rule::Block);
+        assert_eq!(rules.rule_for("Spotify", None), Rule::Block);
+        rules.set("Spotify", Rule::Allow);
+        assert!(rules.apps.is_empty());
+
+        assert_eq!("silent".parse::<Rule>().unwrap(), Rule::Silent);
+        assert!("mute".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Wormhole").join("rules.json");
+
+        assert_eq!(Rules::load(&path).unwrap(), Rules::default());
+
+        let mut rules = rules(true, &[("Spotify", Rule::Silent), ("Steam", Rule::Block)]);
+        rules.category_groups.remove("email");
+        rules.save(&path).unwrap();
+        assert_eq!(Rules::load(&path).unwrap(), rules);
+
+        // settings that are missing from the file get their defaults.
+        fs::write(&path, r#"{ "apps": { "Steam": "block" } }"#).unwrap();
+        assert_eq!(
+            Rules::load(&path).unwrap(),
+            self::rules(false, &[("Steam", Rule::Block)])
+        );
+    }
+
+    #[test]
+    fn test_inhibitions() {
+        let mut inhibitions = Inhibitions::default();
+        assert!(!inhibitions.is_inhibited());
+
+        let a = inhibitions.add(Some(String::from(":1.42")));
+        let b = inhibitions.add(Some(String::from(":1.42")));
+        let c = inhibitions.add(None);
+        assert_ne!(a, 0);
+        assert_ne!(a, b);
+        assert!(inhibitions.is_inhibited());
+
+        assert!(inhibitions.remove_owner(":1.42"));
+        assert!(!inhibitions.remove_owner(":1.42"));
+        assert!(!inhibitions.remove(a));
+        assert!(inhibitions.is_inhibited());
+
+        assert!(inhibitions.remove(c));
+        assert!(!inhibitions.is_inhibited());
+    }
+}
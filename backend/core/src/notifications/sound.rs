@@ -0,0 +1,186 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Maps the sound hints of a notification to the audio of a toast.
+//!
+//! Names come from the freedesktop sound naming spec, and are mapped to the closest of the few
+//! sounds that Windows has for toasts. Toasts can't play other sound files, so those are played
+//! next to a silent toast instead.
+
+use std::path::{Path, PathBuf};
+
+/// The formats that Windows can play without installing extra codecs.
+const SUPPORTED_EXTENSIONS: &[&str] = &["wav", "mp3", "m4a", "wma", "aac"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Audio {
+    /// One of the `ms-winsoundevent:` sounds.
+    Event {
+        name: &'static str,
+        /// Looping sounds keep playing for as long as the toast is shown.
+        looping: bool,
+    },
+    /// A sound file, which is played by the backend while the toast itself is silent.
+    File(PathBuf),
+    Silent,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio::Event {
+            name: "Notification.Default",
+            looping: false,
+        }
+    }
+}
+
+impl Audio {
+    /// Returns the sound event that is the closest match for a sound name.
+    pub fn from_name(name: &str) -> Self {
+        let (name, looping) = match name {
+            "message-new-instant" | "message" | "message-sent-instant" => {
+                ("Notification.IM", false)
+            }
+            "message-new-email" | "message-sent-email" => ("Notification.Mail", false),
+            "message-new-sms" => ("Notification.SMS", false),
+            "alarm-clock-elapsed" => ("Notification.Looping.Alarm", true),
+            "phone-incoming-call" => ("Notification.Looping.Call", true),
+            "dialog-warning" | "dialog-error" | "battery-caution" | "battery-low"
+            | "power-unplug" | "bell" | "bell-window-system" => ("Notification.Reminder", false),
+            _ => return Self::default(),
+        };
+
+        Audio::Event { name, looping }
+    }
+
+    /// Returns the sound event that is the closest match for a sound file.
+    ///
+    /// Sound themes name their files after the sounds, such as `message-new-instant.oga`, so
+    /// those are matched by name. Anything else plays the default sound.
+    pub fn from_file(path: &Path) -> Self {
+        path.file_stem()
+            .and_then(|name| name.to_str())
+            .map(Self::from_name)
+            .unwrap_or_default()
+    }
+
+    /// Returns true if the sound file is in a format that can be played.
+    pub fn is_supported_file(path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                SUPPORTED_EXTENSIONS
+                    .iter()
+                    .any(|supported| supported.eq_ignore_ascii_case(ext))
+            })
+    }
+
+    pub fn is_looping(&self) -> bool {
+        matches!(self, Audio::Event { looping: true, .. })
+    }
+
+    /// Returns the attributes of the `audio` element of a toast.
+    pub fn attributes(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Audio::Event { name, looping } => vec![
+                ("src", format!("ms-winsoundevent:{}", name)),
+                ("loop", looping.to_string()),
+            ],
+            Audio::File(_) | Audio::Silent => vec![("silent", String::from("true"))],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert_eq!(
+            Audio::from_name("message-new-instant"),
+            Audio::Event {
+                name: "Notification.IM",
+                looping: false
+            }
+        );
+        assert_eq!(
+            Audio::from_name("message-new-email"),
+            Audio::Event {
+                name: "Notification.Mail",
+                looping: false
+            }
+        );
+        assert_eq!(
+            Audio::from_name("bell"),
+            Audio::Event {
+                name: "Notification.Reminder",
+                looping: false
+            }
+        );
+        assert_eq!(Audio::from_name("no-such-sound"), Audio::default());
+        assert_eq!(Audio::from_name(""), Audio::default());
+    }
+
+    #[test]
+    fn test_looping() {
+        assert!(Audio::from_name("alarm-clock-elapsed").is_looping());
+        assert!(Audio::from_name("phone-incoming-call").is_looping());
+        assert!(!Audio::from_name("message-new-instant").is_looping());
+        assert!(!Audio::Silent.is_looping());
+    }
+
+    #[test]
+    fn test_attributes() {
+        assert_eq!(
+            Audio::from_name("alarm-clock-elapsed").attributes(),
+            [
+                (
+                    "src",
+                    String::from("ms-winsoundevent:Notification.Looping.Alarm")
+                ),
+                ("loop", String::from("true")),
+            ]
+        );
+        assert_eq!(
+            Audio::Silent.attributes(),
+            [("silent", String::from("true"))]
+        );
+        // the file is played next to the toast.
+        assert_eq!(
+            Audio::File(PathBuf::from(r"C:\Users\me\ding.wav")).attributes(),
+            [("silent", String::from("true"))]
+        );
+    }
+
+    #[test]
+    fn test_supported_files() {
+        assert!(Audio::is_supported_file(Path::new("/home/me/ding.wav")));
+        assert!(Audio::is_supported_file(Path::new("/home/me/DING.MP3")));
+        assert!(!Audio::is_supported_file(Path::new(
+            "/usr/share/sounds/freedesktop/stereo/bell.oga"
+        )));
+        assert!(!Audio::is_supported_file(Path::new("/home/me/ding")));
+        assert!(!Audio::File(PathBuf::from("/home/me/ding.wav")).is_looping());
+    }
+
+    #[test]
+    fn test_files() {
+        assert_eq!(
+            Audio::from_file(Path::new(
+                "/usr/share/sounds/freedesktop/stereo/message-new-instant.oga"
+            )),
+            Audio::from_name("message-new-instant")
+        );
+        assert!(
+            Audio::from_file(Path::new("/usr/share/sounds/alarm-clock-elapsed.wav")).is_looping()
+        );
+        assert_eq!(
+            Audio::from_file(Path::new("/home/me/ding.wav")),
+            Audio::default()
+        );
+        assert_eq!(Audio::from_file(Path::new("/")), Audio::default());
+    }
+}
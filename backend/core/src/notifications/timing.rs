@@ -0,0 +1,206 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Decides how long a toast stays on screen, based on the timeout and urgency of the notification.
+
+use std::time::Duration;
+
+/// Toasts with a short duration are shown for about 7 seconds, long toasts for about 25 seconds.
+const SHORT_DURATION: Duration = Duration::from_secs(7);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl From<u8> for Urgency {
+    fn from(urgency: u8) -> Self {
+        match urgency {
+            0 => Urgency::Low,
+            2 => Urgency::Critical,
+            _ => Urgency::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastDuration {
+    Short,
+    Long,
+}
+
+impl ToastDuration {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ToastDuration::Short => "short",
+            ToastDuration::Long => "long",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    Default,
+    /// Stays on screen until the user dismisses it.
+    Reminder,
+}
+
+impl Scenario {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scenario::Default => "default",
+            Scenario::Reminder => "reminder",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub duration: ToastDuration,
+    pub scenario: Scenario,
+    /// When the notification should be closed, counted from when it is shown.
+    pub expires_after: Option<Duration>,
+    /// Whether the notification stays open in the action center after the toast has left the
+    /// screen, instead of being closed as expired.
+    pub stays_in_action_center: bool,
+}
+
+/// Maps the `expire_timeout` of a notification to how the toast is shown.
+///
+/// `expire_timeout` is in milliseconds, -1 leaves it up to the server, and 0 means that the
+/// notification never expires. Critical notifications stay on screen until they are dismissed,
+/// as the spec asks.
+pub fn timing(expire_timeout: i32, urgency: Urgency) -> Timing {
+    if urgency == Urgency::Critical {
+        return Timing {
+            duration: ToastDuration::Long,
+            scenario: Scenario::Reminder,
+            expires_after: None,
+            stays_in_action_center: true,
+        };
+    }
+
+    // the toast still leaves the screen, but stays in the action center.
+    if expire_timeout == 0 {
+        return Timing {
+            duration: ToastDuration::Long,
+            scenario: Scenario::Default,
+            expires_after: None,
+            stays_in_action_center: true,
+        };
+    }
+
+    if expire_timeout < 0 {
+        return Timing {
+            duration: ToastDuration::Short,
+            scenario: Scenario::Default,
+            expires_after: None,
+            stays_in_action_center: false,
+        };
+    }
+
+    let timeout = Duration::from_millis(expire_timeout as u64);
+    Timing {
+        duration: if timeout <= SHORT_DURATION {
+            ToastDuration::Short
+        } else {
+            ToastDuration::Long
+        },
+        scenario: Scenario::Default,
+        expires_after: Some(timeout),
+        // until it expires.
+        stays_in_action_center: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_timeout() {
+        for urgency in [Urgency::Low, Urgency::Normal] {
+            assert_eq!(
+                timing(-1, urgency),
+                Timing {
+                    duration: ToastDuration::Short,
+                    scenario: Scenario::Default,
+                    expires_after: None,
+                    stays_in_action_center: false,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_never_expires() {
+        let expected = Timing {
+            duration: ToastDuration::Long,
+            scenario: Scenario::Default,
+            expires_after: None,
+            stays_in_action_center: true,
+        };
+
+        assert_eq!(timing(0, Urgency::Normal), expected);
+        assert_eq!(timing(0, Urgency::Low), expected);
+    }
+
+    #[test]
+    fn test_critical() {
+        let expected = Timing {
+            duration: ToastDuration::Long,
+            scenario: Scenario::Reminder,
+            expires_after: None,
+            stays_in_action_center: true,
+        };
+
+        assert_eq!(timing(0, Urgency::Critical), expected);
+        assert_eq!(timing(-1, Urgency::Critical), expected);
+        assert_eq!(timing(5000, Urgency::Critical), expected);
+    }
+
+    #[test]
+    fn test_timeout() {
+        assert_eq!(
+            timing(5000, Urgency::Normal),
+            Timing {
+                duration: ToastDuration::Short,
+                scenario: Scenario::Default,
+                expires_after: Some(Duration::from_secs(5)),
+                stays_in_action_center: true,
+            }
+        );
+        assert_eq!(timing(7000, Urgency::Low).duration, ToastDuration::Short);
+        assert_eq!(
+            timing(60_000, Urgency::Normal),
+            Timing {
+                duration: ToastDuration::Long,
+                scenario: Scenario::Default,
+                expires_after: Some(Duration::from_secs(60)),
+                stays_in_action_center: true,
+            }
+        );
+        assert_eq!(timing(i32::MIN, Urgency::Normal).expires_after, None);
+    }
+
+    #[test]
+    fn test_action_center() {
+        // notifications that never expire are still open after the toast has left the screen.
+        assert!(timing(0, Urgency::Normal).stays_in_action_center);
+        assert!(timing(10_000, Urgency::Low).stays_in_action_center);
+        // with the default timeout, the notification expires when the toast leaves the screen.
+        assert!(!timing(-1, Urgency::Normal).stays_in_action_center);
+    }
+
+    #[test]
+    fn test_urgency() {
+        assert_eq!(Urgency::from(0), Urgency::Low);
+        assert_eq!(Urgency::from(1), Urgency::Normal);
+        assert_eq!(Urgency::from(2), Urgency::Critical);
+        assert_eq!(Urgency::from(42), Urgency::Normal);
+    }
+}
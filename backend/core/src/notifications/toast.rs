@@ -0,0 +1,834 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! The content of a toast, and how notifications map onto it.
+//!
+//! Toasts are serialized to the XML that Windows expects, see
+//! https://docs.microsoft.com/en-us/windows/apps/design/shell/tiles-and-notifications/toast-schema
+//! The limits of the schema are checked here, since Windows only tells us that the XML is invalid.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{bail, Context};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+use super::{
+    grouping::Group,
+    markup::Body,
+    progress,
+    sound::Audio,
+    timing::{Scenario, Timing, ToastDuration},
+};
+
+/// Toasts can't have more buttons than this.
+pub const MAX_ACTIONS: usize = 5;
+
+/// Toasts can't have more text inputs than this.
+const MAX_INPUTS: usize = 5;
+
+/// The generic template has room for this many lines of text, not counting the attribution.
+const MAX_TEXTS: usize = 3;
+
+/// The action that asks for a text input, from KDE's extension of the spec.
+pub const REPLY_ACTION: &str = "inline-reply";
+
+/// The id of the text input for inline replies.
+pub const REPLY_INPUT: &str = "reply";
+
+/// The action that is invoked when the toast itself is clicked.
+const DEFAULT_ACTION: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Placement {
+    /// Replaces the logo of the app.
+    AppLogoOverride,
+    /// A large image at the top of the toast.
+    Hero,
+}
+
+impl Placement {
+    fn as_str(self) -> &'static str {
+        match self {
+            Placement::AppLogoOverride => "appLogoOverride",
+            Placement::Hero => "hero",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub placement: Placement,
+    pub path: PathBuf,
+}
+
+/// A progress bar. The values are usually placeholders that are bound to data values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressBar {
+    pub value: String,
+    pub value_string_override: Option<String>,
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationType {
+    /// Activates the toast, and passes the arguments to us.
+    Foreground,
+    /// Opens the arguments as a URI.
+    Protocol,
+    /// Handled by Windows, such as `dismiss`.
+    System,
+}
+
+impl ActivationType {
+    fn as_str(self) -> &'static str {
+        match self {
+            ActivationType::Foreground => "foreground",
+            ActivationType::Protocol => "protocol",
+            ActivationType::System => "system",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Action {
+    pub content: String,
+    pub arguments: String,
+    pub activation_type: ActivationType,
+    /// Places the button next to a text input.
+    pub input_id: Option<String>,
+}
+
+impl Action {
+    pub fn foreground(content: &str, arguments: &str) -> Self {
+        Self {
+            content: content.to_string(),
+            arguments: arguments.to_string(),
+            activation_type: ActivationType::Foreground,
+            input_id: None,
+        }
+    }
+}
+
+/// Collapses the toasts with the same header id under a header in the Action Center.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub id: String,
+    pub title: String,
+    /// The arguments that are passed when the header is clicked.
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub id: String,
+    pub placeholder: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toast {
+    /// The arguments that are passed when the toast itself is clicked.
+    pub launch: Option<String>,
+    pub duration: ToastDuration,
+    pub scenario: Scenario,
+    pub header: Option<Header>,
+    /// The first line is the title.
+    pub text: Vec<String>,
+    pub attribution: Option<String>,
+    pub images: Vec<Image>,
+    pub progress: Option<ProgressBar>,
+    pub audio: Audio,
+    pub inputs: Vec<Input>,
+    pub actions: Vec<Action>,
+}
+
+/// The parts of a notification that are shown in a toast.
+pub struct Content<'a> {
+    pub app_name: &'a str,
+    pub summary: &'a str,
+    pub body: &'a Body,
+    pub image: Option<PathBuf>,
+    pub hero_image: Option<PathBuf>,
+    /// Pairs of action keys and labels, as in the freedesktop spec.
+    pub actions: &'a [String],
+    pub timing: &'a Timing,
+    pub audio: &'a Audio,
+    /// Shows a progress bar, with the text bound to the values from [`progress::Progress`].
+    pub progress: bool,
+    pub reply_placeholder: Option<&'a str>,
+    /// The group of the toast, which is given a header if it has a title.
+    pub group: Option<&'a Group>,
+}
+
+impl Toast {
+    pub fn from_notification(content: &Content) -> Self {
+        let (text, progress) = if content.progress {
+            let (title, bar) = progress::bound();
+            (vec![title], Some(bar))
+        } else {
+            let mut text = vec![content.summary.to_string()];
+            if !content.body.text.is_empty() {
+                text.push(content.body.text.clone());
+            }
+            (text, None)
+        };
+
+        let images = [
+            (Placement::AppLogoOverride, &content.image),
+            (Placement::Hero, &content.hero_image),
+        ]
+        .iter()
+        .filter_map(|(placement, path)| {
+            Some(Image {
+                placement: *placement,
+                path: path.as_ref()?.clone(),
+            })
+        })
+        .collect();
+
+        let mut launch = None;
+        let mut inputs = Vec::new();
+        let mut actions = Vec::new();
+
+        for action in content.actions.chunks_exact(2) {
+            let (key, label) = (&action[0], &action[1]);
+            if key == DEFAULT_ACTION {
+                launch = Some(DEFAULT_ACTION.to_string());
+            } else if key == REPLY_ACTION {
+                // the toast has room for one reply, and input ids must be unique.
+                if !inputs.is_empty() {
+                    log::warn!("ignoring duplicate {} action", REPLY_ACTION);
+                    continue;
+                }
+
+                // the text input and its send button come before the other buttons.
+                inputs.push(Input {
+                    id: REPLY_INPUT.to_string(),
+                    placeholder: content.reply_placeholder.map(String::from),
+                });
+                actions.insert(
+                    0,
+                    Action {
+                        input_id: Some(REPLY_INPUT.to_string()),
+                        ..Action::foreground(label, REPLY_ACTION)
+                    },
+                );
+            } else {
+                actions.push(Action::foreground(label, key));
+            }
+        }
+
+        if actions.len() > MAX_ACTIONS {
+            log::warn!(
+                "dropping {} actions that don't fit in the toast",
+                actions.len() - MAX_ACTIONS
+            );
+            actions.truncate(MAX_ACTIONS);
+        }
+
+        // links in the body open in the default browser, without going through us.
+        let free = MAX_ACTIONS - actions.len();
+        actions.extend(content.body.links.iter().take(free).map(|link| Action {
+            activation_type: ActivationType::Protocol,
+            ..Action::foreground(&link.text, &link.href)
+        }));
+
+        // reminders are only kept on screen if they have a button.
+        if content.timing.scenario == Scenario::Reminder && actions.is_empty() {
+            actions.push(Action {
+                activation_type: ActivationType::System,
+                ..Action::foreground("", "dismiss")
+            });
+        }
+
+        Self {
+            launch,
+            duration: content.timing.duration,
+            scenario: content.timing.scenario,
+            header: content.group.and_then(|group| {
+                Some(Header {
+                    id: group.id.clone(),
+                    title: group.title.clone()?,
+                    arguments: group.id.clone(),
+                })
+            }),
+            text,
+            attribution: Some(content.app_name.to_string()).filter(|name| !name.is_empty()),
+            images,
+            progress,
+            audio: content.audio.clone(),
+            inputs,
+            actions,
+        }
+    }
+
+    /// Checks the limits of the toast schema.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.text.is_empty() || self.text.len() > MAX_TEXTS {
+            bail!("toasts have 1 to {} lines of text", MAX_TEXTS);
+        }
+        if self.actions.len() > MAX_ACTIONS {
+            bail!("toasts have at most {} actions", MAX_ACTIONS);
+        }
+        if self.inputs.len() > MAX_INPUTS {
+            bail!("toasts have at most {} inputs", MAX_INPUTS);
+        }
+
+        let mut placements = HashSet::new();
+        for image in &self.images {
+            if !placements.insert(image.placement) {
+                bail!(
+                    "more than one image with {} placement",
+                    image.placement.as_str()
+                );
+            }
+        }
+
+        let mut input_ids = HashSet::new();
+        for input in &self.inputs {
+            if input.id.is_empty() || !input_ids.insert(input.id.as_str()) {
+                bail!("input ids must be unique and not empty");
+            }
+        }
+
+        for action in &self.actions {
+            if let Some(id) = &action.input_id {
+                if !input_ids.contains(id.as_str()) {
+                    bail!("action refers to unknown input {}", id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the toast to XML, after checking that it is valid.
+    pub fn to_xml(&self) -> anyhow::Result<String> {
+        self.validate()?;
+
+        let mut xml = Vec::new();
+        let mut writer = EmitterConfig::new()
+            .perform_indent(true)
+            .write_document_declaration(false)
+            .create_writer(&mut xml);
+
+        self.write(&mut writer)
+            .context("failed to write toast xml")?;
+
+        Ok(String::from_utf8(xml)?)
+    }
+
+    fn write(&self, w: &mut EventWriter<&mut Vec<u8>>) -> xml::writer::Result<()> {
+        let mut toast = XmlEvent::start_element("toast")
+            .attr("duration", self.duration.as_str())
+            .attr("scenario", self.scenario.as_str());
+        if let Some(launch) = &self.launch {
+            toast = toast.attr("launch", launch);
+        }
+        w.write(toast)?;
+
+        if let Some(header) = &self.header {
+            w.write(
+                XmlEvent::start_element("header")
+                    .attr("id", &header.id)
+                    .attr("title", &header.title)
+                    .attr("arguments", &header.arguments),
+            )?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        w.write(XmlEvent::start_element("visual"))?;
+        w.write(XmlEvent::start_element("binding").attr("template", "ToastGeneric"))?;
+
+        for (i, text) in self.text.iter().enumerate() {
+            let id = (i + 1).to_string();
+            w.write(XmlEvent::start_element("text").attr("id", &id))?;
+            w.write(XmlEvent::characters(text))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        if let Some(progress) = &self.progress {
+            let mut element = XmlEvent::start_element("progress").attr("value", &progress.value);
+            if let Some(value_string) = &progress.value_string_override {
+                element = element.attr("valueStringOverride", value_string);
+            }
+            w.write(element.attr("status", &progress.status))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        if let Some(attribution) = &self.attribution {
+            w.write(XmlEvent::start_element("text").attr("placement", "attribution"))?;
+            w.write(XmlEvent::characters(attribution))?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        for image in &self.images {
+            let src = format!("file://{}", image.path.to_string_lossy());
+            w.write(
+                XmlEvent::start_element("image")
+                    .attr("placement", image.placement.as_str())
+                    .attr("src", &src),
+            )?;
+            w.write(XmlEvent::end_element())?;
+        }
+
+        w.write(XmlEvent::end_element())?;
+        w.write(XmlEvent::end_element())?;
+
+        let audio = self.audio.attributes();
+        let mut element = XmlEvent::start_element("audio");
+        for (name, value) in &audio {
+            element = element.attr(*name, value);
+        }
+        w.write(element)?;
+        w.write(XmlEvent::end_element())?;
+
+        if !self.inputs.is_empty() || !self.actions.is_empty() {
+            w.write(XmlEvent::start_element("actions"))?;
+
+            for input in &self.inputs {
+                let mut element = XmlEvent::start_element("input")
+                    .attr("id", &input.id)
+                    .attr("type", "text");
+                if let Some(placeholder) = &input.placeholder {
+                    element = element.attr("placeHolderContent", placeholder);
+                }
+                w.write(element)?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            for action in &self.actions {
+                let mut element = XmlEvent::start_element("action")
+                    .attr("content", &action.content)
+                    .attr("arguments", &action.arguments);
+                if action.activation_type != ActivationType::Foreground {
+                    element = element.attr("activationType", action.activation_type.as_str());
+                }
+                if let Some(input_id) = &action.input_id {
+                    element = element.attr("hint-inputId", input_id);
+                }
+                w.write(element)?;
+                w.write(XmlEvent::end_element())?;
+            }
+
+            w.write(XmlEvent::end_element())?;
+        }
+
+        w.write(XmlEvent::end_element())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::{
+        markup,
+        timing::{self, Urgency},
+    };
+    use super::*;
+
+    fn actions(actions: &[&str]) -> Vec<String> {
+        actions.iter().map(|action| action.to_string()).collect()
+    }
+
+    /// How notifications are shown by default.
+    static TIMING: Timing = Timing {
+        duration: ToastDuration::Short,
+        scenario: Scenario::Default,
+        expires_after: None,
+        stays_in_action_center: false,
+    };
+
+    static AUDIO: Audio = Audio::Event {
+        name: "Notification.Default",
+        looping: false,
+    };
+
+    /// Builds the toast for a notification, with the defaults for everything that isn't given.
+    fn toast<'a>(summary: &'a str, body: &'a Body, edit: impl FnOnce(&mut Content<'a>)) -> String {
+        let mut content = Content {
+            app_name: "notify-send",
+            summary,
+            body,
+            image: None,
+            hero_image: None,
+            actions: &[],
+            timing: &TIMING,
+            audio: &AUDIO,
+            progress: false,
+            reply_placeholder: None,
+            group: None,
+        };
+        edit(&mut content);
+        Toast::from_notification(&content).to_xml().unwrap()
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(
+            toast(
+                "Build finished",
+                &markup::parse("All 42 tests passed"),
+                |_| {}
+            ),
+            r#"<toast duration="short" scenario="default">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">Build finished</text>
+      <text id="2">All 42 tests passed</text>
+      <text placement="attribution">notify-send</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+</toast>"#
+        );
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(
+            toast("<b>\"Tom & Jerry\"</b>", &Body::default(), |c| {
+                c.app_name = ""
+            }),
+            r#"<toast duration="short" scenario="default">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">&lt;b&gt;"Tom &amp; Jerry"&lt;/b&gt;</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+</toast>"#
+        );
+    }
+
+    #[test]
+    fn test_markup() {
+        assert_eq!(
+            toast(
+                "New release",
+                &markup::parse(
+                    r#"<b>Wormhole</b> is out, see <a href="https://example.com/?a=1&amp;b=2">the notes</a>"#
+                ),
+                |_| {}
+            ),
+            r#"<toast duration="short" scenario="default">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">New release</text>
+      <text id="2">Wormhole is out, see the notes</text>
+      <text placement="attribution">notify-send</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+  <actions>
+    <action content="the notes" arguments="https://example.com/?a=1&amp;b=2" activationType="protocol" />
+  </actions>
+</toast>"#
+        );
+    }
+
+    #[test]
+    fn test_images() {
+        assert_eq!(
+            toast("Photo", &Body::default(), |c| {
+                c.image = Some(PathBuf::from(r"C:\Temp\Wormhole\cache\avatar.png"));
+                c.hero_image = Some(PathBuf::from(r"C:\Temp\Wormhole\cache\photo & co.jpg"));
+            }),
+            r#"<toast duration="short" scenario="default">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">Photo</text>
+      <text placement="attribution">notify-send</text>
+      <image placement="appLogoOverride" src="file://C:\Temp\Wormhole\cache\avatar.png" />
+      <image placement="hero" src="file://C:\Temp\Wormhole\cache\photo &amp; co.jpg" />
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+</toast>"#
+        );
+    }
+
+    #[test]
+    fn test_actions() {
+        let actions = actions(&["default", "Open", "yes", "Yes", "no", "No"]);
+        assert_eq!(
+            toast("Continue?", &Body::default(), |c| c.actions = &actions),
+            r#"<toast duration="short" scenario="default" launch="default">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">Continue?</text>
+      <text placement="attribution">notify-send</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+  <actions>
+    <action content="Yes" arguments="yes" />
+    <action content="No" arguments="no" />
+  </actions>
+</toast>"#
+        );
+    }
+
+    #[test]
+    fn test_too_many_actions() {
+        let actions = actions(&[
+            "1", "One", "2", "Two", "3", "Three", "4", "Four", "5", "Five", "6", "Six",
+        ]);
+        let body = markup::parse(r#"<a href="https://example.com">Link</a>"#);
+        let timing = timing::timing(-1, Urgency::Normal);
+        let toast = Toast::from_notification(&Content {
+            app_name: "",
+            summary: "",
+            body: &body,
+            image: None,
+            hero_image: None,
+            actions: &actions,
+            timing: &timing,
+            audio: &Audio::Silent,
+            progress: false,
+            reply_placeholder: None,
+            group: None,
+        });
+
+        let arguments: Vec<_> = toast.actions.iter().map(|a| a.arguments.as_str()).collect();
+        assert_eq!(arguments, ["1", "2", "3", "4", "5"]);
+        assert!(toast.validate().is_ok());
+    }
+
+    #[test]
+    fn test_reply() {
+        let actions = actions(&["open", "Open", "inline-reply", "Send"]);
+        assert_eq!(
+            toast("Alice", &markup::parse("Are you there?"), |c| {
+                c.actions = &actions;
+                c.reply_placeholder = Some("Reply to Alice");
+            }),
+            r#"<toast duration="short" scenario="default">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">Alice</text>
+      <text id="2">Are you there?</text>
+      <text placement="attribution">notify-send</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+  <actions>
+    <input id="reply" type="text" placeHolderContent="Reply to Alice" />
+    <action content="Send" arguments="inline-reply" hint-inputId="reply" />
+    <action content="Open" arguments="open" />
+  </actions>
+</toast>"#
+        );
+
+        // only the first reply action is kept.
+        let twice = self::actions(&["inline-reply", "Send", "inline-reply", "Reply"]);
+        let xml = toast("Alice", &Body::default(), |c| c.actions = &twice);
+        assert_eq!(xml.matches("<input ").count(), 1);
+        assert!(xml.contains(r#"<action content="Send" arguments="inline-reply""#));
+        assert!(!xml.contains("Reply"));
+    }
+
+    #[test]
+    fn test_timing() {
+        let critical = timing::timing(-1, Urgency::Critical);
+        assert_eq!(
+            toast("Battery low", &Body::default(), |c| c.timing = &critical),
+            r#"<toast duration="long" scenario="reminder">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">Battery low</text>
+      <text placement="attribution">notify-send</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+  <actions>
+    <action content="" arguments="dismiss" activationType="system" />
+  </actions>
+</toast>"#
+        );
+
+        // the expiration is set on the toast, not in the xml.
+        let timing = timing::timing(20_000, Urgency::Normal);
+        assert_eq!(timing.expires_after, Some(Duration::from_secs(20)));
+        assert!(toast("Reminder", &Body::default(), |c| c.timing = &timing)
+            .starts_with(r#"<toast duration="long" scenario="default">"#));
+    }
+
+    #[test]
+    fn test_audio() {
+        let audio = |audio: Audio| {
+            let xml = toast("Sound", &Body::default(), |c| c.audio = &audio);
+            xml.lines()
+                .find(|line| line.contains("<audio"))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+
+        assert_eq!(
+            audio(Audio::from_name("alarm-clock-elapsed")),
+            r#"<audio src="ms-winsoundevent:Notification.Looping.Alarm" loop="true" />"#
+        );
+        assert_eq!(audio(Audio::Silent), r#"<audio silent="true" />"#);
+    }
+
+    #[test]
+    fn test_progress() {
+        assert_eq!(
+            toast("Copying files", &markup::parse("3 of 7"), |c| {
+                c.progress = true
+            }),
+            r#"<toast duration="short" scenario="default">
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">{summary}</text>
+      <progress value="{progressValue}" valueStringOverride="{progressValueString}" status="{progressStatus}" />
+      <text placement="attribution">notify-send</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+</toast>"#
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        let group = Group {
+            id: String::from("org.gnome.Evolution/email"),
+            title: Some(String::from("Evolution: Email")),
+        };
+        assert_eq!(
+            toast("Jane Doe", &markup::parse("Lunch?"), |c| {
+                c.app_name = "Evolution";
+                c.group = Some(&group);
+            }),
+            r#"<toast duration="short" scenario="default">
+  <header id="org.gnome.Evolution/email" title="Evolution: Email" arguments="org.gnome.Evolution/email" />
+  <visual>
+    <binding template="ToastGeneric">
+      <text id="1">Jane Doe</text>
+      <text id="2">Lunch?</text>
+      <text placement="attribution">Evolution</text>
+    </binding>
+  </visual>
+  <audio src="ms-winsoundevent:Notification.Default" loop="false" />
+</toast>"#
+        );
+
+        // toasts that are only grouped by application don't get a header.
+        let group = Group {
+            id: String::from("notify-send"),
+            title: None,
+        };
+        assert!(!toast("Hello", &Body::default(), |c| c.group = Some(&group)).contains("<header"));
+    }
+
+    /// A toast with only a title, to break the limits of.
+    fn plain() -> Toast {
+        let toast = Toast::from_notification(&Content {
+            app_name: "app",
+            summary: "summary",
+            body: &Body::default(),
+            image: None,
+            hero_image: None,
+            actions: &[],
+            timing: &TIMING,
+            audio: &AUDIO,
+            progress: false,
+            reply_placeholder: None,
+            group: None,
+        });
+        assert!(toast.validate().is_ok());
+        toast
+    }
+
+    fn input(id: &str) -> Input {
+        Input {
+            id: String::from(id),
+            placeholder: None,
+        }
+    }
+
+    #[test]
+    fn test_invalid_texts() {
+        let mut toast = plain();
+        toast.text = vec![String::new(); 4];
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "toasts have 1 to 3 lines of text"
+        );
+
+        toast.text.clear();
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "toasts have 1 to 3 lines of text"
+        );
+    }
+
+    #[test]
+    fn test_invalid_actions() {
+        let mut toast = plain();
+        toast.actions = vec![Action::foreground("a", "a"); 6];
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "toasts have at most 5 actions"
+        );
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let mut toast = plain();
+        toast.inputs = (0..6).map(|i| input(&i.to_string())).collect();
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "toasts have at most 5 inputs"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_placement() {
+        let mut toast = plain();
+        let hero = Image {
+            placement: Placement::Hero,
+            path: PathBuf::from("a.png"),
+        };
+        toast.images = vec![hero.clone(), hero];
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "more than one image with hero placement"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_input_id() {
+        let mut toast = plain();
+        toast.inputs = vec![input("reply"), input("reply")];
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "input ids must be unique and not empty"
+        );
+
+        toast.inputs = vec![input("")];
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "input ids must be unique and not empty"
+        );
+    }
+
+    #[test]
+    fn test_unknown_input() {
+        let mut toast = plain();
+        toast.actions = vec![Action {
+            input_id: Some(String::from("reply")),
+            ..Action::foreground("Send", "send")
+        }];
+        assert_eq!(
+            toast.validate().unwrap_err().to_string(),
+            "action refers to unknown input reply"
+        );
+        // the xml is never written for an invalid toast.
+        assert!(toast.to_xml().is_err());
+    }
+}
@@ -95,6 +95,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     log::info!("all services initialized");
 
+    util::knownfolders::send_to_bridge(&connection)
+        .await
+        .unwrap_or_else(|e| log::error!("failed to send known folders to the bridge: {}", e));
+
     handle.await?;
 
     for name in WELL_KNOWN_NAMES {
@@ -0,0 +1,19 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use zbus::dbus_proxy;
+
+#[dbus_proxy(
+    interface = "com.github.raytar.Files",
+    default_service = "com.github.raytar.Files",
+    default_path = "/com/github/raytar/Files"
+)]
+pub trait Files {
+    fn stat(&self, path: &str) -> zbus::Result<(u64, u64)>;
+
+    fn read(&self, path: &str, offset: u64, length: u64) -> zbus::Result<Vec<u8>>;
+
+    fn find_desktop_entry(&self, desktop_entry: &str) -> zbus::Result<String>;
+}
@@ -3,7 +3,9 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
+pub mod files;
 pub mod icons;
 pub mod menu;
 pub mod status_notifier_item;
 pub mod status_notifier_watcher;
+pub mod userdirs;
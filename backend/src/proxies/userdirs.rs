@@ -0,0 +1,17 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::collections::HashMap;
+
+use zbus::dbus_proxy;
+
+#[dbus_proxy(
+    interface = "com.github.raytar.UserDirs",
+    default_service = "com.github.raytar.UserDirs",
+    default_path = "/com/github/raytar/UserDirs"
+)]
+pub trait UserDirs {
+    fn set_known_folders(&self, folders: HashMap<&str, &str>) -> zbus::Result<()>;
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Registers the AppUserModelIDs that toasts are shown under.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use windows::Win32::{
+    Foundation::PWSTR,
+    System::Registry::{
+        RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_CURRENT_USER, KEY_WRITE,
+        REG_OPTION_NON_VOLATILE, REG_SZ,
+    },
+};
+use zbus::Connection;
+
+use super::appid::{self, DesktopEntry, Registration, DEFAULT_APP_ID};
+use crate::{
+    proxies::{files::FilesProxy, icons::IconsProxy},
+    util::{as_win32_result, remotefiles::RemoteFiles},
+    Config,
+};
+
+/// The size of the icon that is looked up for an application.
+const ICON_SIZE: u16 = 256;
+
+/// How long to wait before trying to register an application again, since the bridge may have
+/// failed for a moment.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+enum State {
+    Registered(String),
+    Failed(Instant),
+}
+
+pub struct AppIds {
+    files_proxy: FilesProxy<'static>,
+    icons: IconsProxy<'static>,
+    files: RemoteFiles,
+    /// Whether each application has been registered, by desktop entry.
+    registered: Mutex<HashMap<String, State>>,
+}
+
+impl AppIds {
+    pub async fn new(connection: &Connection) -> zbus::Result<Self> {
+        Ok(Self {
+            files_proxy: FilesProxy::new(connection).await?,
+            icons: IconsProxy::new(connection).await?,
+            files: RemoteFiles::new(connection).await?,
+            registered: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the AppUserModelID to show the toasts of an application under, registering it the
+    /// first time.
+    ///
+    /// Applications that can't be registered share the default AppUserModelID.
+    pub async fn get(&self, desktop_entry: Option<&str>) -> String {
+        let desktop_entry = match desktop_entry.filter(|entry| !entry.is_empty()) {
+            Some(desktop_entry) => desktop_entry,
+            None => return DEFAULT_APP_ID.to_string(),
+        };
+
+        match self
+            .registered
+            .lock()
+            .expect("poisoned mutex")
+            .get(desktop_entry)
+        {
+            Some(State::Registered(app_id)) => return app_id.clone(),
+            Some(State::Failed(at)) if at.elapsed() < RETRY_DELAY => {
+                return DEFAULT_APP_ID.to_string()
+            }
+            _ => {}
+        }
+
+        // the lock isn't held while registering, so an application that sends several
+        // notifications at once may be registered more than once, which is harmless.
+        let (state, app_id) = match self.register(desktop_entry).await {
+            Ok(app_id) => (State::Registered(app_id.clone()), app_id),
+            Err(err) => {
+                log::warn!("failed to register {}: {}", desktop_entry, err);
+                (State::Failed(Instant::now()), DEFAULT_APP_ID.to_string())
+            }
+        };
+        self.registered
+            .lock()
+            .expect("poisoned mutex")
+            .insert(desktop_entry.to_string(), state);
+
+        app_id
+    }
+
+    async fn register(&self, desktop_entry: &str) -> anyhow::Result<String> {
+        let path = self.files_proxy.find_desktop_entry(desktop_entry).await?;
+        let contents = fs::read(self.files.fetch(&path).await?)?;
+        let entry = DesktopEntry::parse(&String::from_utf8_lossy(&contents))
+            .ok_or_else(|| anyhow::anyhow!("{} has no name", path))?;
+
+        let distro = Config::global().distro_name();
+        let app_id = appid::app_user_model_id(distro, desktop_entry);
+
+        // the application is still worth registering without its icon.
+        let icon = match &entry.icon {
+            Some(icon) => self
+                .get_icon_path(&app_id, icon)
+                .await
+                .unwrap_or_else(|err| {
+                    log::debug!("failed to get icon {}: {}", icon, err);
+                    None
+                }),
+            None => None,
+        };
+
+        let registration = Registration::new(distro, desktop_entry, &entry, icon.as_deref());
+        write_registration(&registration)?;
+
+        log::info!("registered {} as {}", desktop_entry, registration.app_id);
+
+        Ok(registration.app_id)
+    }
+
+    /// Returns a copy of the icon of an application that stays for as long as it is registered,
+    /// unlike the files in the cache.
+    async fn get_icon_path(&self, app_id: &str, icon: &str) -> anyhow::Result<Option<PathBuf>> {
+        let path = self.icons.lookup_icon(icon, ICON_SIZE).await?;
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let data = fs::read(self.files.fetch(&path).await?)?;
+
+        let mut icon_path = icons_dir().ok_or_else(|| anyhow!("LOCALAPPDATA is not set"))?;
+        icon_path.push(appid::icon_file_name(app_id, &path));
+
+        // Windows may be using the previous copy, which can't be overwritten.
+        if fs::read(&icon_path).ok().as_deref() != Some(&data[..]) {
+            fs::create_dir_all(icon_path.parent().unwrap())?;
+            fs::write(&icon_path, &data)?;
+        }
+
+        Ok(Some(icon_path))
+    }
+}
+
+/// Returns where the icons of registered applications are kept.
+fn icons_dir() -> Option<PathBuf> {
+    let mut path = PathBuf::from(std::env::var_os("LOCALAPPDATA")?);
+    path.push("Wormhole");
+    path.push("icons");
+    Some(path)
+}
+
+fn write_registration(registration: &Registration) -> windows::core::Result<()> {
+    let mut key = HKEY::default();
+    as_win32_result(unsafe {
+        RegCreateKeyExW(
+            HKEY_CURRENT_USER,
+            registration.key.as_str(),
+            0,
+            PWSTR::default(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_WRITE,
+            std::ptr::null(),
+            &mut key,
+            std::ptr::null_mut(),
+        )
+    })?;
+    let key = scopeguard::guard(key, |key| unsafe {
+        RegCloseKey(key);
+    });
+
+    for (name, value) in &registration.values {
+        let data: Vec<u16> = value.encode_utf16().chain(std::iter::once(0)).collect();
+        as_win32_result(unsafe {
+            RegSetValueExW(
+                *key,
+                *name,
+                0,
+                REG_SZ,
+                data.as_ptr() as *const u8,
+                (data.len() * std::mem::size_of::<u16>()) as u32,
+            )
+        })?;
+    }
+
+    Ok(())
+}
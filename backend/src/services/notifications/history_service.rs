@@ -0,0 +1,167 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Lets tools inside the distro look up the notifications that have been shown.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use zbus::{dbus_interface, fdo, Connection, SignalContext};
+use zvariant_derive::Type;
+
+use super::{
+    history::{Entry, History},
+    NotificationsServiceData, PATH,
+};
+
+/// Changes to the history, which are sent as signals.
+pub enum HistoryEvent {
+    Added(Entry),
+    Updated(Entry),
+}
+
+/// A history entry as it is sent over D-Bus.
+#[derive(Debug, Type, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub seq: u64,
+    pub id: u32,
+    pub app_name: String,
+    pub summary: String,
+    pub body: String,
+    pub hints: HashMap<String, String>,
+    /// Milliseconds since the Unix epoch.
+    pub created: u64,
+    /// Zero while the notification is still shown.
+    pub closed: u64,
+    /// Empty while the notification is still shown.
+    pub outcome: String,
+}
+
+impl From<&Entry> for HistoryEntry {
+    fn from(entry: &Entry) -> Self {
+        Self {
+            seq: entry.seq,
+            id: entry.id,
+            app_name: entry.app_name.clone(),
+            summary: entry.summary.clone(),
+            body: entry.body.clone(),
+            hints: entry
+                .hints
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            created: entry.created,
+            closed: entry.closed.unwrap_or_default(),
+            outcome: entry
+                .outcome
+                .map(|outcome| outcome.as_str().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub struct NotificationHistory {
+    data: Arc<Mutex<NotificationsServiceData>>,
+}
+
+impl NotificationHistory {
+    pub(super) fn new(data: Arc<Mutex<NotificationsServiceData>>) -> Self {
+        Self { data }
+    }
+
+    /// Sends the changes to the history as signals.
+    pub(super) async fn forward_events(
+        connection: Connection,
+        mut rx: mpsc::UnboundedReceiver<HistoryEvent>,
+    ) -> zbus::Result<()> {
+        let ctx = SignalContext::new(&connection, PATH)?;
+
+        while let Some(event) = rx.recv().await {
+            match event {
+                HistoryEvent::Added(entry) => Self::entry_added(&ctx, (&entry).into()).await,
+                HistoryEvent::Updated(entry) => Self::entry_updated(&ctx, (&entry).into()).await,
+            }
+            .unwrap_or_else(|err| log::error!("failed to send history signal: {}", err));
+        }
+
+        Ok(())
+    }
+}
+
+#[dbus_interface(name = "com.github.raytar.NotificationHistory")]
+impl NotificationHistory {
+    /// Returns the newest entries first.
+    ///
+    /// An empty app name matches every application, `since` is in milliseconds since the Unix
+    /// epoch, and a limit of zero returns every entry.
+    fn query(&self, app_name: &str, since: u64, limit: u32) -> Vec<HistoryEntry> {
+        let app_name = Some(app_name).filter(|app_name| !app_name.is_empty());
+        let limit = if limit == 0 {
+            usize::MAX
+        } else {
+            limit as usize
+        };
+
+        let data = self.data.lock().expect("poisoned mutex");
+        data.history
+            .query(app_name, since, limit)
+            .into_iter()
+            .map(HistoryEntry::from)
+            .collect()
+    }
+
+    async fn clear(&self, #[zbus(signal_context)] ctx: SignalContext<'_>) -> fdo::Result<()> {
+        log::debug!("clearing notification history");
+
+        {
+            let mut data = self.data.lock().expect("poisoned mutex");
+            data.history.clear();
+            data.save_history();
+        }
+        Self::cleared(&ctx).await?;
+
+        Ok(())
+    }
+
+    /// Whether the history is kept across restarts.
+    #[dbus_interface(property)]
+    fn persistent(&self) -> bool {
+        self.data
+            .lock()
+            .expect("poisoned mutex")
+            .history_path
+            .is_some()
+    }
+
+    #[dbus_interface(property)]
+    fn set_persistent(&self, value: bool) {
+        let mut data = self.data.lock().expect("poisoned mutex");
+        if value {
+            data.history_path = History::default_path();
+            data.save_history();
+        } else if let Some(path) = data.history_path.take() {
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    log::error!("failed to remove notification history: {}", err)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[dbus_interface(signal)]
+    async fn entry_added(ctx: &SignalContext<'_>, entry: HistoryEntry) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn entry_updated(ctx: &SignalContext<'_>, entry: HistoryEntry) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn cleared(ctx: &SignalContext<'_>) -> zbus::Result<()>;
+}
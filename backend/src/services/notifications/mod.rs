@@ -3,34 +3,97 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
+mod appids;
+mod history_service;
+pub mod portal;
+mod settings;
 mod toasthelper;
 
 use std::{
     collections::{BTreeMap, HashMap},
     convert::TryFrom,
-    env, fs,
-    path::PathBuf,
-    sync::Mutex,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::Duration,
 };
 
-use image::{DynamicImage, ImageFormat, RgbImage, RgbaImage};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use futures::StreamExt;
+use image::ImageOutputFormat;
+use scopeguard::ScopeGuard;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use windows::UI::Notifications::ToastDismissalReason;
-use zbus::{dbus_interface, Connection, SignalContext};
+use zbus::{dbus_interface, fdo, Connection, MessageHeader, SignalContext};
 use zvariant::{OwnedValue, Value};
 use zvariant_derive::Type;
 
-use self::toasthelper::ToastHelper;
-use crate::{proxies::icons::IconsProxy, util::wslpath};
+use backend_core::notifications::{
+    appid, gnotification, grouping, history, imagedata, markup, progress, rules, sound, timing,
+    toast,
+};
+
+use self::{
+    appids::AppIds,
+    grouping::Group,
+    history::{History, Outcome},
+    history_service::{HistoryEvent, NotificationHistory},
+    imagedata::Image,
+    portal::NotificationPortal,
+    progress::Progress,
+    rules::{Decision, Inhibitions, Rules, Suppression},
+    settings::NotificationSettings,
+    sound::Audio,
+    timing::{ToastDuration, Urgency},
+    toast::{Content, Toast, REPLY_ACTION},
+    toasthelper::ToastHelper,
+};
+use crate::{
+    proxies::icons::IconsProxy,
+    util::{cache::Cache, remotefiles::RemoteFiles},
+};
 
 enum ToastEvent {
-    Activated(String),
+    Activated {
+        action: String,
+        reply: Option<String>,
+    },
     Dismissed(ToastDismissalReason),
     Failed(windows::core::Error),
 }
 
+/// Forwards the events of a toast to a channel, such that they can be handled asynchronously.
+fn toast_events(toast: &ToastHelper) -> windows::core::Result<mpsc::UnboundedReceiver<ToastEvent>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    {
+        let tx = tx.clone();
+        toast.on_activated(move |action, reply| {
+            tx.send(ToastEvent::Activated { action, reply })
+                .unwrap_or_else(|err| log::error!("failed to send toast event: {}", err))
+        })?;
+    }
+
+    {
+        let tx = tx.clone();
+        toast.on_dismissed(move |reason| {
+            tx.send(ToastEvent::Dismissed(reason))
+                .unwrap_or_else(|err| log::error!("failed to send toast event: {}", err))
+        })?;
+    }
+
+    {
+        toast.on_failed(move |err| {
+            tx.send(ToastEvent::Failed(err))
+                .unwrap_or_else(|err| log::error!("failed to send toast event: {}", err))
+        })?;
+    }
+
+    Ok(rx)
+}
+
 enum NotificationClosedReason {
     Expired = 1,
     Dismissed,
@@ -38,15 +101,248 @@ enum NotificationClosedReason {
     Undefined,
 }
 
+impl NotificationClosedReason {
+    fn from_dismissal(reason: ToastDismissalReason) -> Self {
+        if reason == ToastDismissalReason::ApplicationHidden {
+            NotificationClosedReason::Closed
+        } else if reason == ToastDismissalReason::TimedOut {
+            NotificationClosedReason::Expired
+        } else if reason == ToastDismissalReason::UserCanceled {
+            NotificationClosedReason::Dismissed
+        } else {
+            NotificationClosedReason::Undefined
+        }
+    }
+
+    fn outcome(&self) -> Outcome {
+        match self {
+            NotificationClosedReason::Expired => Outcome::Expired,
+            NotificationClosedReason::Dismissed => Outcome::Dismissed,
+            NotificationClosedReason::Closed | NotificationClosedReason::Undefined => {
+                Outcome::Closed
+            }
+        }
+    }
+}
+
+struct ActiveNotification {
+    /// Identifies the toast that is currently shown for the notification, such that events from a
+    /// toast that has since been replaced can be ignored.
+    serial: u64,
+    /// The sequence number of the history entry.
+    history: u64,
+    group: Group,
+    toast: ToastHelper,
+}
+
+/// Identifies each toast that is shown, by both the notification service and the portal.
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+fn next_serial() -> u64 {
+    NEXT_SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+const PATH: &str = "/org/freedesktop/Notifications";
+
+/// How long to wait for more changes before the history is saved.
+const HISTORY_SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// The hints that are kept in the history.
+const HISTORY_HINTS: &[&str] = &[
+    "category",
+    "desktop-entry",
+    "resident",
+    "transient",
+    "urgency",
+    "value",
+];
+
 #[derive(Default)]
 struct NotificationsServiceData {
     next_id: u32,
-    notifications: BTreeMap<u32, ToastHelper>,
+    notifications: BTreeMap<u32, ActiveNotification>,
+    rules: Rules,
+    inhibitions: Inhibitions,
+    history: History,
+    /// Where the history is saved, if it is kept across restarts.
+    history_path: Option<PathBuf>,
+    history_events: Option<mpsc::UnboundedSender<HistoryEvent>>,
+    /// Asks `save_history_task` to save the history.
+    history_saves: Option<mpsc::UnboundedSender<()>>,
+}
+
+impl NotificationsServiceData {
+    /// Returns the id to use for a notification, which is `replaces_id` if that notification is
+    /// still active.
+    fn id_for(&mut self, replaces_id: u32) -> u32 {
+        if replaces_id != 0 && self.notifications.contains_key(&replaces_id) {
+            return replaces_id;
+        }
+
+        let id = self.next_id;
+        // zero is never a valid id.
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    /// Stores the toast for a notification, replacing the previous one if there is one.
+    fn insert(&mut self, id: u32, serial: u64, history: u64, group: Group, toast: ToastHelper) {
+        if let Some(replaced) = self.notifications.insert(
+            id,
+            ActiveNotification {
+                serial,
+                history,
+                group,
+                toast,
+            },
+        ) {
+            // a toast in another group is shown next to the previous one instead of replacing it.
+            if !self.notifications[&id].group.replaces(&replaced.group) {
+                replaced
+                    .toast
+                    .dismiss()
+                    .unwrap_or_else(|err| log::error!("failed to dismiss toast: {}", err));
+            }
+            release_files(replaced.serial);
+            self.record_outcome(replaced.history, Outcome::Replaced);
+        }
+    }
+
+    fn remove(&mut self, id: u32, outcome: Outcome) -> Option<ActiveNotification> {
+        let removed = self.notifications.remove(&id)?;
+        release_files(removed.serial);
+        self.record_outcome(removed.history, outcome);
+        Some(removed)
+    }
+
+    /// Removes a notification if its toast has not been replaced.
+    fn remove_current(
+        &mut self,
+        id: u32,
+        serial: u64,
+        outcome: Outcome,
+    ) -> Option<ActiveNotification> {
+        match self.notifications.get(&id) {
+            Some(n) if n.serial == serial => self.remove(id, outcome),
+            _ => None,
+        }
+    }
+
+    /// Adds a notification to the history, and returns the sequence number of its entry.
+    fn record(&mut self, id: u32, notification: &Notification, body: &str) -> u64 {
+        self.add_entry(history::Entry {
+            seq: 0,
+            id,
+            app_name: notification.app_name.clone(),
+            summary: notification.summary.clone(),
+            body: body.to_string(),
+            hints: history_hints(notification),
+            created: history::now(),
+            closed: None,
+            outcome: None,
+        })
+    }
+
+    /// Adds an entry to the history, and returns its sequence number.
+    fn add_entry(&mut self, entry: history::Entry) -> u64 {
+        let entry = self.history.add(entry);
+        let seq = entry.seq;
+
+        if let Some(tx) = &self.history_events {
+            tx.send(HistoryEvent::Added(entry)).ok();
+        }
+        self.save_history();
+
+        seq
+    }
+
+    /// Updates the entry of a notification that was changed without showing it again.
+    fn update_entry(&mut self, seq: u64, notification: &Notification, body: &str) {
+        let hints = history_hints(notification);
+        if let Some(entry) = self.history.update(seq, &notification.summary, body, hints) {
+            if let Some(tx) = &self.history_events {
+                tx.send(HistoryEvent::Updated(entry.clone())).ok();
+            }
+            self.save_history();
+        }
+    }
+
+    fn record_outcome(&mut self, seq: u64, outcome: Outcome) {
+        if let Some(entry) = self.history.close(seq, outcome, history::now()) {
+            if let Some(tx) = &self.history_events {
+                tx.send(HistoryEvent::Updated(entry.clone())).ok();
+            }
+            self.save_history();
+        }
+    }
+
+    fn save_history(&self) {
+        if self.history_path.is_some() {
+            if let Some(tx) = &self.history_saves {
+                tx.send(()).ok();
+            }
+        }
+    }
+}
+
+/// Saves the history when it has changed, once per burst of changes, and without holding the lock
+/// while the file is written.
+async fn save_history_task(
+    data: Weak<Mutex<NotificationsServiceData>>,
+    mut rx: mpsc::UnboundedReceiver<()>,
+) {
+    while rx.recv().await.is_some() {
+        tokio::time::sleep(HISTORY_SAVE_DELAY).await;
+        while rx.try_recv().is_ok() {}
+
+        let data = match data.upgrade() {
+            Some(data) => data,
+            None => return,
+        };
+        let (path, json) = {
+            let data = data.lock().expect("poisoned mutex");
+            match &data.history_path {
+                Some(path) => (path.clone(), data.history.to_json()),
+                None => continue,
+            }
+        };
+
+        tokio::task::spawn_blocking(move || History::write(&path, &json?))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result)
+            .unwrap_or_else(|err| log::error!("failed to save notification history: {}", err));
+    }
+}
+
+/// Returns the hints of a notification that are kept in the history.
+fn history_hints(notification: &Notification) -> BTreeMap<String, String> {
+    HISTORY_HINTS
+        .iter()
+        .filter_map(|name| {
+            let value = hint_to_string(notification.hints.get(*name)?)?;
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+/// Converts the simple hints to strings, for the history.
+fn hint_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::U8(n) => Some(n.to_string()),
+        Value::I32(n) => Some(n.to_string()),
+        Value::U32(n) => Some(n.to_string()),
+        _ => None,
+    }
 }
 
 pub struct Notifications {
     icons: Box<IconsProxy<'static>>,
-    data: Mutex<NotificationsServiceData>,
+    files: RemoteFiles,
+    app_ids: AppIds,
+    data: Arc<Mutex<NotificationsServiceData>>,
 }
 
 impl Notifications {
@@ -55,88 +351,361 @@ impl Notifications {
             .request_name("org.freedesktop.Notifications")
             .await?;
 
+        let rules_path = Rules::default_path();
+        let rules = rules_path
+            .as_deref()
+            .map(|path| {
+                Rules::load(path).unwrap_or_else(|err| {
+                    log::error!("failed to load notification rules: {}", err);
+                    Rules::default()
+                })
+            })
+            .unwrap_or_default();
+
+        // the history is only kept across restarts if it has been saved before.
+        let history_path = History::default_path();
+        let history = history_path
+            .as_deref()
+            .map(|path| {
+                History::load(path, history::DEFAULT_MAX_ENTRIES).unwrap_or_else(|err| {
+                    log::error!("failed to load notification history: {}", err);
+                    None
+                })
+            })
+            .unwrap_or_default();
+        let history_path = history_path.filter(|_| history.is_some());
+
+        let (history_tx, history_rx) = mpsc::unbounded_channel();
+        tokio::spawn(NotificationHistory::forward_events(
+            connection.clone(),
+            history_rx,
+        ));
+
+        let (save_tx, save_rx) = mpsc::unbounded_channel();
+        let data = Arc::new(Mutex::new(NotificationsServiceData {
+            next_id: 1,
+            rules,
+            history: history.unwrap_or_default(),
+            history_path,
+            history_events: Some(history_tx),
+            history_saves: Some(save_tx),
+            ..Default::default()
+        }));
+        tokio::spawn(save_history_task(Arc::downgrade(&data), save_rx));
+
         connection
             .object_server()
             .at(
-                "/org/freedesktop/Notifications",
+                PATH,
                 Notifications {
                     icons: Box::new(IconsProxy::new(connection).await?),
-                    data: Mutex::new(NotificationsServiceData {
-                        next_id: 1,
-                        notifications: BTreeMap::new(),
-                    }),
+                    files: RemoteFiles::new(connection).await?,
+                    app_ids: AppIds::new(connection).await?,
+                    data: data.clone(),
                 },
             )
             .await?;
+        connection
+            .object_server()
+            .at(PATH, NotificationSettings::new(data.clone(), rules_path))
+            .await?;
+        connection
+            .object_server()
+            .at(PATH, NotificationHistory::new(data.clone()))
+            .await?;
+        // the portal shares the rules and history of the notification service.
+        NotificationPortal::init(connection, data).await?;
+
+        {
+            let connection = connection.clone();
+            tokio::spawn(async move {
+                Self::handle_name_owner_changed(connection)
+                    .await
+                    .unwrap_or_else(|e| log::error!("{}", e))
+            });
+        }
 
         log::info!("org.freedesktop.Notifications server enabled");
 
         Ok(())
     }
 
+    /// Releases the inhibitions of applications that go away without releasing them.
+    async fn handle_name_owner_changed(connection: Connection) -> zbus::Result<()> {
+        let dbus = fdo::DBusProxy::new(&connection).await?;
+        let mut name_owner_changed_stream = dbus.receive_name_owner_changed().await?;
+
+        while let Some(signal) = name_owner_changed_stream.next().await {
+            let args = signal.args()?;
+            if args.new_owner().is_some() {
+                continue;
+            }
+
+            let iface = connection
+                .object_server()
+                .interface::<_, Notifications>(PATH)
+                .await?;
+            let notifications = iface.get().await;
+
+            let released = notifications
+                .data
+                .lock()
+                .expect("poisoned mutex")
+                .inhibitions
+                .remove_owner(args.name());
+            if released {
+                log::info!("released the inhibitions of {}", args.name());
+                notifications
+                    .inhibited_changed(iface.signal_context())
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Closes a notification that is not shown, with the reason that matches why it wasn't.
+    fn suppress(
+        &self,
+        ctx: &SignalContext<'_>,
+        notification: &Notification,
+        body: &str,
+        suppression: Suppression,
+    ) -> u32 {
+        log::info!(
+            "suppressed notification from {} ({})",
+            notification.app_name,
+            suppression.as_str()
+        );
+
+        let mut data = self.data.lock().expect("poisoned mutex");
+        let id = data.id_for(notification.replaces_id);
+        // the notification that it replaces would be out of date.
+        if let Some(replaced) = data.remove(id, Outcome::Replaced) {
+            replaced
+                .toast
+                .dismiss()
+                .unwrap_or_else(|err| log::error!("failed to dismiss toast: {}", err));
+        }
+        let seq = data.record(id, notification, body);
+        data.record_outcome(seq, suppression.into());
+        drop(data);
+
+        // blocking is the user's choice, while the others make the notification go by unseen.
+        let reason = match suppression {
+            Suppression::Blocked => NotificationClosedReason::Dismissed,
+            Suppression::DoNotDisturb | Suppression::Inhibited => NotificationClosedReason::Expired,
+        };
+
+        // the signal has to come after the reply with the id.
+        let ctx = SignalContext::from_parts(ctx.connection().clone(), ctx.path().to_owned());
+        tokio::spawn(async move {
+            Self::notification_closed(&ctx, id, reason as _)
+                .await
+                .unwrap_or_else(|err| log::error!("failed to send notification signal: {}", err))
+        });
+
+        id
+    }
+
     async fn notify_internal(
         &self,
         ctx: SignalContext<'_>,
         notification: Notification,
     ) -> anyhow::Result<u32> {
-        let image_path = self.get_image_path(&notification).await?;
+        let body = markup::parse(&notification.body);
+
+        let urgency = notification
+            .hints
+            .get("urgency")
+            .and_then(|value| u8::try_from(value.clone()).ok())
+            .map(Urgency::from)
+            .unwrap_or(Urgency::Normal);
+
+        let desktop_entry = notification
+            .hints
+            .get("desktop-entry")
+            .and_then(|value| <&str>::try_from(value).ok());
+
+        let decision = {
+            let data = self.data.lock().expect("poisoned mutex");
+            data.rules.decide(
+                &notification.app_name,
+                desktop_entry,
+                urgency,
+                data.inhibitions.is_inhibited(),
+            )
+        };
+        if let Decision::Suppress(suppression) = decision {
+            return Ok(self.suppress(&ctx, &notification, &body.text, suppression));
+        }
+        let progress = notification
+            .hints
+            .get("value")
+            .and_then(|value| i32::try_from(value.clone()).ok())
+            .map(Progress::new);
+
+        // progress updates are pushed to the toast that is already shown, instead of showing it
+        // again.
+        if let Some(progress) = &progress {
+            let mut data = self.data.lock().expect("poisoned mutex");
+            if let Some(n) = data.notifications.get_mut(&notification.replaces_id) {
+                if n.toast.has_progress()
+                    && n.toast
+                        .update(progress.values(&notification.summary, &body.text))?
+                {
+                    let seq = n.history;
+                    data.update_entry(seq, &notification, &body.text);
+                    return Ok(notification.replaces_id);
+                }
+            }
+        }
+
+        let serial = next_serial();
+        // the files that belong to the toast are removed if it's never shown.
+        let files = scopeguard::guard(serial, release_files);
+
+        let image_path = self.get_image_path(&notification, serial).await?;
+
+        let hero_image = match &body.image {
+            // the notification is still useful without the image.
+            Some(path) => match self.files.fetch_owned(path, &files_owner(serial)).await {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    log::warn!("failed to fetch image {}: {}", path, err);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let mut timing = timing::timing(notification.expire_timeout, urgency);
+
+        let audio = if decision == Decision::ShowSilently {
+            Audio::Silent
+        } else {
+            self.get_audio(&notification, serial).await
+        };
+        // looping sounds are only allowed for long toasts.
+        if audio.is_looping() {
+            timing.duration = ToastDuration::Long;
+        }
+
+        let app_id = self.app_ids.get(desktop_entry).await;
 
         let mut data = self.data.lock().expect("poisoned mutex");
 
-        let id = data.next_id;
-        data.next_id += 1;
+        let id = data.id_for(notification.replaces_id);
 
-        let toast = ToastHelper::new(
-            &id.to_string(),
+        let group = Group::new(
+            &data.rules.category_groups,
+            desktop_entry.unwrap_or(&notification.app_name),
             &notification.app_name,
-            &notification.summary,
-            &notification.body,
-            image_path,
-            &notification.actions,
+            notification
+                .hints
+                .get("category")
+                .and_then(|value| <&str>::try_from(value).ok()),
+        );
+
+        let toast = Toast::from_notification(&Content {
+            app_name: &notification.app_name,
+            summary: &notification.summary,
+            body: &body,
+            image: image_path,
+            hero_image,
+            actions: &notification.actions,
+            timing: &timing,
+            audio: &audio,
+            progress: progress.is_some(),
+            reply_placeholder: notification
+                .hints
+                .get("x-kde-reply-placeholder-text")
+                .and_then(|value| <&str>::try_from(value).ok()),
+            group: Some(&group),
+        });
+        let mut toast = ToastHelper::new(
+            &app_id,
+            &id.to_string(),
+            &group.id,
+            &toast,
+            progress.map(|progress| progress.values(&notification.summary, &body.text)),
+            timing.expires_after,
         )?;
 
-        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut rx = toast_events(&toast)?;
 
-        {
-            let tx = tx.clone();
-            toast.on_activated(move |action| {
-                tx.send(ToastEvent::Activated(action))
-                    .unwrap_or_else(|err| log::error!("failed to send toast event: {}", err))
-            })?;
+        // showing a toast with the same tag replaces the previous one in place.
+        toast.show()?;
+        if let Audio::File(path) = &audio {
+            toast
+                .play_sound(path)
+                .unwrap_or_else(|err| log::warn!("failed to play sound file: {}", err));
         }
+        let history = data.record(id, &notification, &body.text);
+        data.insert(id, serial, history, group, toast);
+        drop(data);
+        ScopeGuard::into_inner(files);
 
-        {
-            let tx = tx.clone();
-            toast.on_dismissed(move |reason| {
-                tx.send(ToastEvent::Dismissed(reason))
-                    .unwrap_or_else(|err| log::error!("failed to send toast event: {}", err))
-            })?;
-        }
+        let ctx = SignalContext::from_parts(ctx.connection().clone(), ctx.path().to_owned());
+        let data = self.data.clone();
 
-        {
-            toast.on_failed(move |err| {
-                tx.send(ToastEvent::Failed(err))
-                    .unwrap_or_else(|err| log::error!("failed to send toast event: {}", err))
-            })?;
-        }
+        if let Some(expires_after) = timing.expires_after {
+            let ctx = ctx.clone();
+            let data = data.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(expires_after).await;
 
-        let ctx = SignalContext::from_parts(ctx.connection().clone(), ctx.path().to_owned());
+                let removed = data.lock().expect("poisoned mutex").remove_current(
+                    id,
+                    serial,
+                    Outcome::Expired,
+                );
+                if let Some(n) = removed {
+                    n.toast
+                        .dismiss()
+                        .unwrap_or_else(|err| log::error!("failed to dismiss toast: {}", err));
+                    Self::notification_closed(&ctx, id, NotificationClosedReason::Expired as _)
+                        .await
+                        .unwrap_or_else(|err| {
+                            log::error!("failed to send notification signal: {}", err)
+                        });
+                }
+            });
+        }
 
         tokio::spawn(async move {
-            if let Some(event) = rx.recv().await {
-                match event {
-                    ToastEvent::Activated(action) => Self::action_invoked(&ctx, id, &action).await,
+            while let Some(event) = rx.recv().await {
+                // toasts that time out are moved to the action center, and stay there until they
+                // expire, if they ever do.
+                if let ToastEvent::Dismissed(reason) = &event {
+                    if *reason == ToastDismissalReason::TimedOut && timing.stays_in_action_center {
+                        continue;
+                    }
+                }
+
+                let outcome = match &event {
+                    ToastEvent::Activated { .. } => Outcome::Activated,
                     ToastEvent::Dismissed(reason) => {
-                        let reason = if reason == ToastDismissalReason::ApplicationHidden {
-                            NotificationClosedReason::Closed
-                        } else if reason == ToastDismissalReason::TimedOut {
-                            NotificationClosedReason::Expired
-                        } else if reason == ToastDismissalReason::UserCanceled {
-                            NotificationClosedReason::Dismissed
-                        } else {
-                            NotificationClosedReason::Undefined
-                        };
+                        NotificationClosedReason::from_dismissal(*reason).outcome()
+                    }
+                    ToastEvent::Failed(_) => Outcome::Failed,
+                };
+
+                // the notification may have been closed or replaced in the meantime.
+                let removed = data
+                    .lock()
+                    .expect("poisoned mutex")
+                    .remove_current(id, serial, outcome);
+                if removed.is_none() {
+                    return;
+                }
 
+                match event {
+                    ToastEvent::Activated { action, reply } => {
+                        Self::activated(&ctx, id, &action, reply.as_deref()).await
+                    }
+                    ToastEvent::Dismissed(reason) => {
+                        let reason = NotificationClosedReason::from_dismissal(reason);
                         Self::notification_closed(&ctx, id, reason as _).await
                     }
                     ToastEvent::Failed(err) => {
@@ -153,43 +722,231 @@ impl Notifications {
             }
         });
 
-        toast.show()?;
-
         Ok(id)
     }
 
-    async fn get_image_path(&self, notification: &Notification) -> anyhow::Result<Option<PathBuf>> {
-        if let Some(value) = notification.hints.get("image-data") {
-            let image = Image::try_from(value.clone())?;
-            let path = image_to_file(image)?;
+    async fn activated(
+        ctx: &SignalContext<'_>,
+        id: u32,
+        action: &str,
+        reply: Option<&str>,
+    ) -> zbus::Result<()> {
+        if action == REPLY_ACTION {
+            Self::notification_replied(ctx, id, reply.unwrap_or_default()).await?;
+        }
+        Self::action_invoked(ctx, id, action).await?;
+
+        // the toast is gone once it has been activated.
+        Self::notification_closed(ctx, id, NotificationClosedReason::Dismissed as _).await
+    }
+
+    async fn get_audio(&self, notification: &Notification, serial: u64) -> Audio {
+        let hint = |name| notification.hints.get(name).cloned();
+
+        if let Some(true) = hint("suppress-sound").and_then(|value| bool::try_from(value).ok()) {
+            return Audio::Silent;
+        }
+
+        let file = hint("sound-file").and_then(|value| String::try_from(value).ok());
+        if let Some(path) = &file {
+            if Audio::is_supported_file(Path::new(path)) {
+                match self.files.fetch_owned(path, &files_owner(serial)).await {
+                    Ok(path) => return Audio::File(path),
+                    Err(err) => log::warn!("failed to fetch sound file {}: {}", path, err),
+                }
+            }
+        }
+
+        if let Some(name) = hint("sound-name").and_then(|value| String::try_from(value).ok()) {
+            return Audio::from_name(&name);
+        }
+
+        // formats that Windows can't play, such as the .oga files of sound themes.
+        if let Some(path) = &file {
+            log::debug!("playing the closest sound to {}", path);
+            return Audio::from_file(Path::new(path));
+        }
+
+        Audio::default()
+    }
+
+    async fn get_image_path(
+        &self,
+        notification: &Notification,
+        serial: u64,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        // older versions of the spec use different names for the hints.
+        let hint = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| notification.hints.get(*name))
+                .cloned()
+        };
+
+        if let Some(value) = hint(&["image-data", "image_data"]) {
+            let image = Image::try_from(value)?;
+            let path = image_to_file(&image, serial)?;
             Ok(Some(path))
-        } else if let Some(value) = notification.hints.get("image-path") {
-            Ok(Some(wslpath::get_temp_copy(&String::try_from(
-                value.clone(),
-            )?)?))
-        } else if notification.app_icon.is_empty() {
-            Ok(None)
-        } else {
+        } else if let Some(value) = hint(&["image-path", "image_path"]) {
+            let path = String::try_from(value)?;
+            Ok(Some(
+                self.files.fetch_owned(&path, &files_owner(serial)).await?,
+            ))
+        } else if !notification.app_icon.is_empty() {
             let path = self.icons.lookup_icon(&notification.app_icon, 128).await?;
-            Ok(Some(wslpath::get_temp_copy(&path)?))
+            Ok(Some(
+                self.files.fetch_owned(&path, &files_owner(serial)).await?,
+            ))
+        } else if let Some(value) = hint(&["icon_data"]) {
+            let image = Image::try_from(value)?;
+            let path = image_to_file(&image, serial)?;
+            Ok(Some(path))
+        } else {
+            Ok(None)
         }
     }
 }
 
 #[dbus_interface(name = "org.freedesktop.Notifications")]
 impl Notifications {
-    async fn close_notification(&self, id: u32) {
+    async fn close_notification(
+        &self,
+        #[zbus(signal_context)] ctx: SignalContext<'_>,
+        id: u32,
+    ) -> zbus::fdo::Result<()> {
         log::debug!("close_notification {:#?}", id);
 
-        let mut data = self.data.lock().unwrap();
-        if let Some(n) = data.notifications.remove(&id) {
-            n.dismiss().unwrap_or_else(|err| log::error!("{}", err));
+        let removed = self.data.lock().unwrap().remove(id, Outcome::Closed);
+        if let Some(n) = removed {
+            n.toast
+                .dismiss()
+                .unwrap_or_else(|err| log::error!("failed to dismiss toast: {}", err));
+            Self::notification_closed(&ctx, id, NotificationClosedReason::Closed as _).await?;
         }
+
+        Ok(())
+    }
+
+    /// Closes every notification in a group, as returned by `GetGroups`.
+    async fn close_group(
+        &self,
+        #[zbus(signal_context)] ctx: SignalContext<'_>,
+        group: &str,
+    ) -> fdo::Result<()> {
+        log::debug!("close_group {}", group);
+
+        let removed: Vec<_> = {
+            let mut data = self.data.lock().expect("poisoned mutex");
+            let ids: Vec<_> = data
+                .notifications
+                .iter()
+                .filter(|(_, n)| n.group.id == group)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| Some((id, data.remove(id, Outcome::Closed)?)))
+                .collect()
+        };
+
+        for (id, n) in removed {
+            n.toast
+                .dismiss()
+                .unwrap_or_else(|err| log::error!("failed to dismiss toast: {}", err));
+            Self::notification_closed(&ctx, id, NotificationClosedReason::Closed as _).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the groups that have notifications, as the group, the title of its header and the
+    /// ids of the notifications in it.
+    ///
+    /// Groups without a header have an empty title.
+    fn get_groups(&self) -> Vec<(String, String, Vec<u32>)> {
+        let data = self.data.lock().expect("poisoned mutex");
+
+        let mut groups: Vec<(String, String, Vec<u32>)> = Vec::new();
+        for (id, n) in &data.notifications {
+            match groups.iter_mut().find(|(group, _, _)| *group == n.group.id) {
+                Some((_, _, ids)) => ids.push(*id),
+                None => groups.push((
+                    n.group.id.clone(),
+                    n.group.title.clone().unwrap_or_default(),
+                    vec![*id],
+                )),
+            }
+        }
+        groups
     }
 
     fn get_capabilities(&self) -> Vec<&str> {
         log::debug!("get_capabilities");
-        vec!["actions", "body", "icon-static", "persistence"]
+        vec![
+            "actions",
+            "body",
+            "body-hyperlinks",
+            "body-images",
+            "body-markup",
+            "icon-static",
+            "inline-reply",
+            "persistence",
+            "sound",
+        ]
+    }
+
+    async fn inhibit(
+        &self,
+        #[zbus(header)] hdr: MessageHeader<'_>,
+        #[zbus(signal_context)] ctx: SignalContext<'_>,
+        desktop_entry: &str,
+        reason: &str,
+        _hints: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<u32> {
+        log::info!("{} inhibits notifications: {}", desktop_entry, reason);
+
+        let owner = hdr.sender()?.map(|sender| sender.to_string());
+        let cookie = self
+            .data
+            .lock()
+            .expect("poisoned mutex")
+            .inhibitions
+            .add(owner);
+        self.inhibited_changed(&ctx).await?;
+
+        Ok(cookie)
+    }
+
+    async fn un_inhibit(
+        &self,
+        #[zbus(signal_context)] ctx: SignalContext<'_>,
+        cookie: u32,
+    ) -> fdo::Result<()> {
+        log::debug!("un_inhibit {}", cookie);
+
+        let removed = self
+            .data
+            .lock()
+            .expect("poisoned mutex")
+            .inhibitions
+            .remove(cookie);
+        if !removed {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "no inhibition with cookie {}",
+                cookie
+            )));
+        }
+        self.inhibited_changed(&ctx).await?;
+
+        Ok(())
+    }
+
+    #[dbus_interface(property)]
+    fn inhibited(&self) -> bool {
+        self.data
+            .lock()
+            .expect("poisoned mutex")
+            .inhibitions
+            .is_inhibited()
     }
 
     fn get_server_information(&self) -> ServerInformation {
@@ -220,6 +977,10 @@ impl Notifications {
     #[dbus_interface(signal)]
     async fn action_invoked(ctx: &SignalContext<'_>, id: u32, action_key: &str)
         -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn notification_replied(ctx: &SignalContext<'_>, id: u32, text: &str)
+        -> zbus::Result<()>;
 }
 
 #[derive(Debug, Type, Serialize, Deserialize)]
@@ -260,58 +1021,31 @@ pub struct Notification {
     pub expire_timeout: i32,
 }
 
-#[derive(Clone, Debug, Type, Serialize, Deserialize, Value, OwnedValue)]
-pub struct Image {
-    pub width: i32,
-    pub height: i32,
-    pub rowstride: i32,
-    pub has_alpha: bool,
-    pub bits_per_sample: i32,
-    pub channels: i32,
-    pub data: Vec<u8>,
-}
-
-fn image_to_file(image: Image) -> anyhow::Result<PathBuf> {
-    let i = if image.has_alpha {
-        DynamicImage::ImageRgba8(
-            RgbaImage::from_raw(image.width as _, image.height as _, image.data).unwrap(),
-        )
-    } else {
-        DynamicImage::ImageRgb8(
-            RgbImage::from_raw(image.width as _, image.height as _, image.data).unwrap(),
-        )
-    };
-
-    let mut path = env::temp_dir();
-    path.push("Wormhole");
-    path.push("notify-images");
+/// Images sent with the notification are kept until the toast they belong to is gone.
+fn image_to_file(image: &Image, serial: u64) -> anyhow::Result<PathBuf> {
+    let i = imagedata::decode(image)?;
 
-    if !path.exists() {
-        fs::create_dir_all(&path)?;
-    }
-
-    if !path.is_dir() {
-        panic!("expected a directory!");
-    }
+    let mut png = Vec::new();
+    i.write_to(&mut png, ImageOutputFormat::Png)?;
 
-    path.push(random_string(12) + ".png");
+    let path = Cache::global().insert_owned(&files_owner(serial), &png, "png")?;
 
     log::debug!(
-        "saving image ({}x{}) to file: {}",
+        "saved image ({}x{}) to file: {}",
         image.width,
         image.height,
         path.display()
     );
 
-    i.save_with_format(&path, ImageFormat::Png)?;
-
     Ok(path)
 }
 
-fn random_string(n: usize) -> String {
-    thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(n)
-        .map(char::from)
-        .collect()
+fn files_owner(serial: u64) -> String {
+    format!("notification-{}", serial)
+}
+
+fn release_files(serial: u64) {
+    Cache::global()
+        .release(&files_owner(serial))
+        .unwrap_or_else(|err| log::error!("failed to remove notification files: {}", err));
 }
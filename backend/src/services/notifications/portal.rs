@@ -0,0 +1,414 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! The backend of the notification portal, which is used by sandboxed applications and by
+//! applications that use GNotification.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use scopeguard::ScopeGuard;
+use sha2::{Digest, Sha256};
+use windows::UI::Notifications::ToastDismissalReason;
+use zbus::{dbus_interface, fdo, Connection, SignalContext};
+use zvariant::OwnedValue;
+
+use super::{
+    appids::AppIds,
+    gnotification::{Action, GNotification, Icon},
+    grouping::Group,
+    history,
+    markup::Body,
+    rules::{Decision, Suppression},
+    sound::Audio,
+    timing,
+    toast::{Content, Toast},
+    toasthelper::ToastHelper,
+    NotificationsServiceData, ToastEvent,
+};
+use crate::{
+    proxies::icons::IconsProxy,
+    util::{cache::Cache, remotefiles::RemoteFiles},
+};
+
+/// The toast action that is invoked when the toast itself is clicked.
+const DEFAULT_ACTION: &str = "default";
+
+/// Notifications are identified by the application that sent them, and the id it gave them.
+type Key = (String, String);
+
+struct PortalNotification {
+    /// Identifies the toast that is currently shown for the notification, such that events from a
+    /// toast that has since been replaced can be ignored.
+    serial: u64,
+    toast: ToastHelper,
+    default_action: Option<Action>,
+    /// The actions of the buttons, which are invoked by their index.
+    buttons: Vec<Action>,
+}
+
+impl PortalNotification {
+    fn action(&self, arguments: &str) -> Option<&Action> {
+        if arguments == DEFAULT_ACTION {
+            self.default_action.as_ref()
+        } else {
+            self.buttons.get(arguments.parse::<usize>().ok()?)
+        }
+    }
+}
+
+#[derive(Default)]
+struct NotificationPortalData {
+    notifications: HashMap<Key, PortalNotification>,
+}
+
+impl NotificationPortalData {
+    /// Stores a notification, replacing the previous one with the same key if there is one.
+    fn insert(&mut self, key: Key, notification: PortalNotification) {
+        if let Some(replaced) = self.notifications.insert(key, notification) {
+            super::release_files(replaced.serial);
+        }
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<PortalNotification> {
+        let removed = self.notifications.remove(key)?;
+        super::release_files(removed.serial);
+        Some(removed)
+    }
+
+    /// Removes a notification if its toast has not been replaced.
+    fn remove_current(&mut self, key: &Key, serial: u64) -> Option<PortalNotification> {
+        match self.notifications.get(key) {
+            Some(n) if n.serial == serial => self.remove(key),
+            _ => None,
+        }
+    }
+}
+
+pub struct NotificationPortal {
+    icons: Box<IconsProxy<'static>>,
+    files: RemoteFiles,
+    app_ids: AppIds,
+    data: Arc<Mutex<NotificationPortalData>>,
+    /// The notification service, which has the rules and the history.
+    service: Arc<Mutex<NotificationsServiceData>>,
+}
+
+impl NotificationPortal {
+    pub(super) async fn init(
+        connection: &Connection,
+        service: Arc<Mutex<NotificationsServiceData>>,
+    ) -> zbus::Result<()> {
+        connection
+            .object_server()
+            .at(
+                crate::services::PORTAL_PATH,
+                NotificationPortal {
+                    icons: Box::new(IconsProxy::new(connection).await?),
+                    files: RemoteFiles::new(connection).await?,
+                    app_ids: AppIds::new(connection).await?,
+                    data: Default::default(),
+                    service,
+                },
+            )
+            .await?;
+
+        log::info!("Notification portal enabled.");
+
+        Ok(())
+    }
+
+    async fn add_notification_internal(
+        &self,
+        ctx: SignalContext<'_>,
+        app_id: String,
+        id: String,
+        notification: HashMap<String, OwnedValue>,
+    ) -> anyhow::Result<()> {
+        let notification = GNotification::try_from(&notification)?;
+
+        // the app id of the portal is the desktop entry of the application.
+        let decision = {
+            let service = self.service.lock().expect("poisoned mutex");
+            service.rules.decide(
+                &app_id,
+                Some(&app_id),
+                notification.urgency,
+                service.inhibitions.is_inhibited(),
+            )
+        };
+        if let Decision::Suppress(suppression) = decision {
+            self.suppress((app_id, id), &notification, suppression);
+            return Ok(());
+        }
+
+        let serial = super::next_serial();
+        // the files that belong to the toast are removed if it's never shown.
+        let files = scopeguard::guard(serial, super::release_files);
+
+        let image_path = match &notification.icon {
+            // the notification is still useful without the icon.
+            Some(icon) => self
+                .get_icon_path(icon, serial)
+                .await
+                .unwrap_or_else(|err| {
+                    log::warn!("failed to get notification icon: {}", err);
+                    None
+                }),
+            None => None,
+        };
+
+        // GNotification doesn't have timeouts, so the toasts stay in the action center until they
+        // are withdrawn.
+        let timing = timing::timing(-1, notification.urgency);
+
+        let mut actions = Vec::new();
+        if notification.default_action.is_some() {
+            actions.extend([DEFAULT_ACTION.to_string(), String::new()]);
+        }
+        for (i, button) in notification.buttons.iter().enumerate() {
+            actions.extend([i.to_string(), button.label.clone()]);
+        }
+
+        let audio = if decision == Decision::ShowSilently {
+            Audio::Silent
+        } else {
+            Audio::default()
+        };
+
+        let body = Body {
+            text: notification.body,
+            links: Vec::new(),
+            image: None,
+        };
+
+        let toast = Toast::from_notification(&Content {
+            app_name: &app_id,
+            summary: &notification.title,
+            body: &body,
+            image: image_path,
+            hero_image: None,
+            actions: &actions,
+            timing: &timing,
+            audio: &audio,
+            progress: false,
+            reply_placeholder: None,
+            group: None,
+        });
+        let toast = ToastHelper::new(
+            &self.app_ids.get(Some(&app_id)).await,
+            &tag(&app_id, &id),
+            &Group::app(&app_id).id,
+            &toast,
+            None,
+            None,
+        )?;
+
+        let mut rx = super::toast_events(&toast)?;
+
+        // showing a toast with the same tag replaces the previous one in place.
+        toast.show()?;
+        let key = (app_id, id);
+        self.data.lock().expect("poisoned mutex").insert(
+            key.clone(),
+            PortalNotification {
+                serial,
+                toast,
+                default_action: notification.default_action,
+                buttons: notification
+                    .buttons
+                    .into_iter()
+                    .map(|button| button.action)
+                    .collect(),
+            },
+        );
+        ScopeGuard::into_inner(files);
+
+        let ctx = SignalContext::from_parts(ctx.connection().clone(), ctx.path().to_owned());
+        let data = self.data.clone();
+
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                // toasts that time out are moved to the action center, where they can still be
+                // activated.
+                if let ToastEvent::Dismissed(reason) = &event {
+                    if *reason == ToastDismissalReason::TimedOut {
+                        continue;
+                    }
+                }
+
+                // the notification may have been removed or replaced in the meantime.
+                let removed = data
+                    .lock()
+                    .expect("poisoned mutex")
+                    .remove_current(&key, serial);
+                let removed = match removed {
+                    Some(removed) => removed,
+                    None => return,
+                };
+
+                match event {
+                    ToastEvent::Activated { action, .. } => {
+                        if let Some(action) = removed.action(&action) {
+                            Self::activated(&ctx, &key.0, &key.1, action)
+                                .await
+                                .unwrap_or_else(|err| {
+                                    log::error!("failed to send notification signal: {}", err)
+                                });
+                        }
+                    }
+                    ToastEvent::Dismissed(_) => {}
+                    ToastEvent::Failed(err) => log::error!("toast notification failed: {}", err),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Records a notification that is not shown, and withdraws the one that it replaces.
+    fn suppress(&self, key: Key, notification: &GNotification, suppression: Suppression) {
+        log::info!(
+            "suppressed notification from {} ({})",
+            key.0,
+            suppression.as_str()
+        );
+
+        // the notification that it replaces would be out of date.
+        let replaced = self.data.lock().expect("poisoned mutex").remove(&key);
+        if let Some(replaced) = replaced {
+            replaced
+                .toast
+                .dismiss()
+                .unwrap_or_else(|err| log::error!("failed to dismiss toast: {}", err));
+        }
+
+        let mut service = self.service.lock().expect("poisoned mutex");
+        // portal notifications have no numeric id.
+        let seq = service.add_entry(history::Entry {
+            seq: 0,
+            id: 0,
+            app_name: key.0.clone(),
+            summary: notification.title.clone(),
+            body: notification.body.clone(),
+            hints: vec![
+                (String::from("desktop-entry"), key.0),
+                (
+                    String::from("urgency"),
+                    (notification.urgency as u8).to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            created: history::now(),
+            closed: None,
+            outcome: None,
+        });
+        service.record_outcome(seq, suppression.into());
+    }
+
+    async fn activated(
+        ctx: &SignalContext<'_>,
+        app_id: &str,
+        id: &str,
+        action: &Action,
+    ) -> zbus::Result<()> {
+        // only application actions can be invoked through the portal.
+        let name = match action.name.strip_prefix("app.") {
+            Some(name) => name,
+            None => {
+                log::warn!("ignoring action {} from {}", action.name, app_id);
+                return Ok(());
+            }
+        };
+
+        let parameter = action.target.iter().cloned().collect();
+        Self::action_invoked(ctx, app_id, id, name, parameter).await
+    }
+
+    async fn get_icon_path(&self, icon: &Icon, serial: u64) -> anyhow::Result<Option<PathBuf>> {
+        let owner = super::files_owner(serial);
+        match icon {
+            Icon::Themed(names) => {
+                for name in names {
+                    match self.icons.lookup_icon(name, 128).await {
+                        Ok(path) => return Ok(Some(self.files.fetch_owned(&path, &owner).await?)),
+                        Err(err) => log::debug!("failed to look up icon {}: {}", name, err),
+                    }
+                }
+                Ok(None)
+            }
+            Icon::Bytes(bytes) => {
+                let extension = image::guess_format(bytes)?.extensions_str()[0];
+                let path = Cache::global().insert_owned(&owner, bytes, extension)?;
+                Ok(Some(path))
+            }
+            Icon::File(path) => Ok(Some(self.files.fetch_owned(path, &owner).await?)),
+        }
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.Notification")]
+impl NotificationPortal {
+    async fn add_notification(
+        &self,
+        #[zbus(signal_context)] ctx: SignalContext<'_>,
+        app_id: String,
+        id: String,
+        notification: HashMap<String, OwnedValue>,
+    ) -> fdo::Result<()> {
+        log::debug!("add_notification {} {}", app_id, id);
+
+        self.add_notification_internal(ctx, app_id, id, notification)
+            .await
+            .map_err(|err| {
+                log::error!("add_notification failed: {}", err);
+                fdo::Error::Failed(err.to_string())
+            })
+    }
+
+    async fn remove_notification(&self, app_id: String, id: String) -> fdo::Result<()> {
+        log::debug!("remove_notification {} {}", app_id, id);
+
+        let removed = self
+            .data
+            .lock()
+            .expect("poisoned mutex")
+            .remove(&(app_id, id));
+        if let Some(n) = removed {
+            n.toast
+                .dismiss()
+                .unwrap_or_else(|err| log::error!("failed to dismiss toast: {}", err));
+        }
+
+        Ok(())
+    }
+
+    #[dbus_interface(signal)]
+    async fn action_invoked(
+        ctx: &SignalContext<'_>,
+        app_id: &str,
+        id: &str,
+        action: &str,
+        parameter: Vec<OwnedValue>,
+    ) -> zbus::Result<()>;
+}
+
+/// Returns the tag of the toast for a notification.
+///
+/// Tags are short, so the key is hashed. The prefix keeps them apart from the tags of the
+/// notification service.
+fn tag(app_id: &str, id: &str) -> String {
+    let hash = Sha256::new()
+        .chain_update(app_id)
+        .chain_update([0])
+        .chain_update(id)
+        .finalize();
+    format!("portal-{:x}", hash)[..("portal-".len() + 16)].to_string()
+}
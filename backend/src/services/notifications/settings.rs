@@ -0,0 +1,146 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Lets the user mute applications, turn on do not disturb, and choose how notifications are grouped.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use zbus::{dbus_interface, fdo, SignalContext};
+
+use super::{rules::Rule, NotificationsServiceData};
+
+pub struct NotificationSettings {
+    data: Arc<Mutex<NotificationsServiceData>>,
+    /// Where the rules are saved when they change.
+    path: Option<PathBuf>,
+}
+
+impl NotificationSettings {
+    pub(super) fn new(data: Arc<Mutex<NotificationsServiceData>>, path: Option<PathBuf>) -> Self {
+        Self { data, path }
+    }
+
+    fn save(&self, data: &NotificationsServiceData) {
+        if let Some(path) = &self.path {
+            data.rules
+                .save(path)
+                .unwrap_or_else(|err| log::error!("failed to save notification rules: {}", err));
+        }
+    }
+}
+
+#[dbus_interface(name = "com.github.raytar.NotificationSettings")]
+impl NotificationSettings {
+    /// Sets the rule for an application, by its desktop entry or its name.
+    ///
+    /// The rule is one of "allow", "silent" or "block".
+    async fn set_app_rule(
+        &self,
+        #[zbus(signal_context)] ctx: SignalContext<'_>,
+        app: &str,
+        rule: &str,
+    ) -> fdo::Result<()> {
+        let rule = rule
+            .parse::<Rule>()
+            .map_err(|err| fdo::Error::InvalidArgs(err.to_string()))?;
+
+        {
+            let mut data = self.data.lock().expect("poisoned mutex");
+            data.rules.set(app, rule);
+            self.save(&data);
+        }
+        self.app_rules_changed(&ctx).await?;
+
+        Ok(())
+    }
+
+    /// Groups the notifications of a category, such as `email.arrived`, or of a class of
+    /// categories, such as `email`, under a header with the given title.
+    ///
+    /// An empty title stops grouping them.
+    async fn set_category_group(
+        &self,
+        #[zbus(signal_context)] ctx: SignalContext<'_>,
+        category: &str,
+        title: &str,
+    ) -> fdo::Result<()> {
+        if category.is_empty() {
+            return Err(fdo::Error::InvalidArgs(String::from(
+                "the category must not be empty",
+            )));
+        }
+
+        {
+            let mut data = self.data.lock().expect("poisoned mutex");
+            data.rules.set_category_group(category, title);
+            self.save(&data);
+        }
+        self.category_groups_changed(&ctx).await?;
+
+        Ok(())
+    }
+
+    /// Returns the notifications that were not shown, oldest first, as the id, application name,
+    /// summary, body and the reason it was suppressed.
+    fn get_suppressed_notifications(&self) -> Vec<(u32, String, String, String, String)> {
+        let data = self.data.lock().expect("poisoned mutex");
+        data.history
+            .iter()
+            .filter_map(|entry| {
+                let outcome = entry.outcome.filter(|outcome| outcome.is_suppressed())?;
+                Some((
+                    entry.id,
+                    entry.app_name.clone(),
+                    entry.summary.clone(),
+                    entry.body.clone(),
+                    outcome.as_str().to_string(),
+                ))
+            })
+            .collect()
+    }
+
+    #[dbus_interface(property)]
+    fn app_rules(&self) -> HashMap<String, String> {
+        let data = self.data.lock().expect("poisoned mutex");
+        data.rules
+            .apps
+            .iter()
+            .map(|(app, rule)| (app.clone(), rule.as_str().to_string()))
+            .collect()
+    }
+
+    /// The titles of the headers that notifications are grouped under, by category.
+    #[dbus_interface(property)]
+    fn category_groups(&self) -> HashMap<String, String> {
+        let data = self.data.lock().expect("poisoned mutex");
+        data.rules
+            .category_groups
+            .iter()
+            .map(|(category, title)| (category.clone(), title.clone()))
+            .collect()
+    }
+
+    #[dbus_interface(property)]
+    fn do_not_disturb(&self) -> bool {
+        self.data
+            .lock()
+            .expect("poisoned mutex")
+            .rules
+            .do_not_disturb
+    }
+
+    #[dbus_interface(property)]
+    fn set_do_not_disturb(&self, value: bool) {
+        log::info!("do not disturb: {}", value);
+
+        let mut data = self.data.lock().expect("poisoned mutex");
+        data.rules.do_not_disturb = value;
+        self.save(&data);
+    }
+}
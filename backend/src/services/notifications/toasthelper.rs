@@ -3,11 +3,12 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-extern crate xml;
-use std::path::PathBuf;
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
 
 use windows::core::{IInspectable, Interface, HSTRING};
-use xml::escape::{escape_str_attribute, escape_str_pcdata};
 
 // You need to have the windows crate in your Cargo.toml
 //
@@ -22,104 +23,103 @@ use xml::escape::{escape_str_attribute, escape_str_pcdata};
 // or have pregenerated code that does the same thing
 use windows::{
     Data::Xml::Dom::XmlDocument,
-    Foundation::TypedEventHandler,
+    Foundation::{DateTime, IPropertyValue, IReference, PropertyValue, TypedEventHandler, Uri},
+    Media::Core::MediaSource,
+    Media::Playback::{MediaPlayer, MediaPlayerAudioCategory},
+    UI::Notifications::{NotificationData, NotificationUpdateResult},
     UI::Notifications::{ToastActivatedEventArgs, ToastFailedEventArgs},
     UI::Notifications::{ToastDismissalReason, ToastNotification},
     UI::Notifications::{ToastDismissedEventArgs, ToastNotificationManager},
 };
 
+use super::toast::{Toast, REPLY_INPUT};
+
 //https://social.msdn.microsoft.com/Forums/Windows/en-US/99e0d4bd-07cb-4ebd-8c92-c44ac6e7e5de/toast-notification-dismissed-event-handler-not-called-every-time?forum=windowsgeneraldevelopmentissues
 pub use windows::core::Error;
 
 pub struct ToastHelper {
     toast: ToastNotification,
+    /// The AppUserModelID that the toast is shown under.
+    app_id: HSTRING,
+    group: HSTRING,
+    /// Set for toasts with a progress bar, which can be updated through their data values.
+    progress: bool,
+    /// Makes sure that updates that arrive out of order are ignored.
+    sequence_number: u32,
+    /// Plays the sound file of the toast, and stops it when the toast is dropped.
+    player: Option<MediaPlayer>,
 }
 
 impl ToastHelper {
+    /// Creates the toast, with the data values for its progress bar if it has one.
     pub fn new(
+        app_id: &str,
         tag: &str,
-        app_name: &str,
-        summary: &str,
-        body: &str,
-        image: Option<PathBuf>,
-        actions: &[String],
+        group: &str,
+        toast: &Toast,
+        progress_values: Option<Vec<(&str, String)>>,
+        expires_after: Option<Duration>,
     ) -> anyhow::Result<ToastHelper> {
-        let image = if let Some(image_path) = image {
-            log::debug!("using image: {}", image_path.as_os_str().to_string_lossy());
-            format!(
-                r#"<image placement="appLogoOverride" src="file://{}" />"#,
-                escape_str_pcdata(&image_path.as_os_str().to_string_lossy()),
-            )
-        } else {
-            String::new()
-        };
-
-        let visual = format!(
-            r#"<visual>
-                <binding template="ToastGeneric">
-                    <text id="1">{heading}</text>
-                    <text id="2">{content}</text>
-                    <text placement="attribution">{app_name}</text>
-                    {image}
-                    <!-- <image placement="appLogoOverride" hint-crop="circle" src="file:///c:/path_to_image_above_toast.jpg" alt="alt text" /> -->
-                    <!-- <image placement="Hero" src="file:///C:/path_to_image_in_toast.jpg" alt="alt text2" /> -->
-                    <!-- <image id="1" src="file:///..." alt="another_image" /> -->
-                </binding>
-            </visual>"#,
-            app_name = escape_str_pcdata(app_name),
-            heading = escape_str_pcdata(summary),
-            content = escape_str_pcdata(body),
-            image = image,
-        );
-
-        let mut actions_xml = String::from("<actions>");
-        let mut launch_arg = "";
-
-        // TODO: the freedesktop notifications spec sends actions in a vector, these should really be paired up since
-        // each even index is an action name, and every odd index is a display name.
-        for action in actions.chunks_exact(2) {
-            if action[0] == "default" {
-                launch_arg = "default";
-            } else {
-                actions_xml.push_str(
-                    format!(
-                        r#"<action content="{content}" arguments="{action}" />"#,
-                        content = escape_str_attribute(&action[1]),
-                        action = escape_str_attribute(&action[0])
-                    )
-                    .as_str(),
-                );
-            }
-        }
-        actions_xml.push_str("</actions>");
-
         let toast_xml = XmlDocument::new()?;
-        let xml = format!(
-            r#"<toast duration="long" launch="{launch}">
-                {visual}
-                <audio src="ms-winsoundevent:Notification.Default" />
-                <!-- <audio silent="true" /> -->
-                <!-- See https://docs.microsoft.com/en-us/windows/uwp/design/shell/tiles-and-notifications/toast-pending-update?tabs=xml for possible actions -->
-                {actions}
-            </toast>"#,
-            launch = launch_arg,
-            visual = visual,
-            actions = actions_xml
-        );
-        toast_xml.LoadXml(xml).expect("the xml is malformed");
+        toast_xml.LoadXml(HSTRING::from(toast.to_xml()?))?;
 
         let toast = ToastNotification::CreateToastNotification(toast_xml)?;
         toast.SetTag(tag)?;
+        toast.SetGroup(group)?;
+
+        let sequence_number = 1;
+        let progress = progress_values.is_some();
+        if let Some(values) = progress_values {
+            toast.SetData(notification_data(values, sequence_number)?)?;
+        }
+
+        // removes the toast from the action center if we're not around to do it.
+        if let Some(expires_after) = expires_after {
+            let expiration_time =
+                PropertyValue::CreateDateTime(to_date_time(SystemTime::now() + expires_after))?;
+            toast.SetExpirationTime(expiration_time.cast::<IReference<DateTime>>()?)?;
+        }
 
-        Ok(ToastHelper { toast })
+        Ok(ToastHelper {
+            toast,
+            app_id: HSTRING::from(app_id),
+            group: HSTRING::from(group),
+            progress,
+            sequence_number,
+            player: None,
+        })
     }
 
-    pub fn on_activated(&self, callback: impl Fn(String) + 'static) -> windows::core::Result<()> {
+    pub fn has_progress(&self) -> bool {
+        self.progress
+    }
+
+    /// Updates the data values of a toast that is already shown.
+    ///
+    /// Returns false if the toast is no longer around.
+    pub fn update(&mut self, values: Vec<(&str, String)>) -> windows::core::Result<bool> {
+        self.sequence_number += 1;
+
+        let toast_notifier = ToastNotificationManager::CreateToastNotifierWithId(&self.app_id)?;
+        let result = toast_notifier.UpdateWithTagAndGroup(
+            notification_data(values, self.sequence_number)?,
+            self.toast.Tag()?,
+            &self.group,
+        )?;
+
+        Ok(result == NotificationUpdateResult::Succeeded)
+    }
+
+    /// Calls `callback` with the arguments of the action, and the text of the inline reply.
+    pub fn on_activated(
+        &self,
+        callback: impl Fn(String, Option<String>) + 'static,
+    ) -> windows::core::Result<()> {
         self.toast.Activated(TypedEventHandler::new(
             move |_, result: &Option<IInspectable>| {
                 if let Some(result) = result {
                     let args = result.cast::<ToastActivatedEventArgs>()?;
-                    callback(args.Arguments()?.to_string());
+                    callback(args.Arguments()?.to_string(), reply_text(&args).ok());
                 }
                 Ok(())
             },
@@ -161,18 +161,59 @@ impl ToastHelper {
     }
 
     pub fn show(&self) -> windows::core::Result<()> {
-        // If you have a valid app id, (ie installed using wix) then use it here.
-        let toast_notifier = ToastNotificationManager::CreateToastNotifierWithId(HSTRING::from(
-            "johnio.wormhole.0.1",
-        ))?;
+        let toast_notifier = ToastNotificationManager::CreateToastNotifierWithId(&self.app_id)?;
 
         // Show the toast.
         // Note this returns success in every case, including when the toast isn't shown.
         toast_notifier.Show(&self.toast)
     }
 
+    /// Plays a sound file, as toasts can only play the sounds of Windows.
+    pub fn play_sound(&mut self, path: &Path) -> windows::core::Result<()> {
+        let uri = Uri::CreateUri(HSTRING::from(format!("file:///{}", path.display())))?;
+
+        let player = MediaPlayer::new()?;
+        player.SetAudioCategory(MediaPlayerAudioCategory::Alerts)?;
+        player.SetSource(MediaSource::CreateFromUri(uri)?)?;
+        player.Play()?;
+
+        self.player = Some(player);
+        Ok(())
+    }
+
     pub fn dismiss(&self) -> windows::core::Result<()> {
         let notification_history = ToastNotificationManager::History()?;
-        notification_history.Remove(self.toast.Tag()?)
+        notification_history.RemoveGroupedTagWithId(self.toast.Tag()?, &self.group, &self.app_id)
     }
 }
+
+/// Converts the time to a WinRT `DateTime`, which counts 100ns intervals since 1601.
+fn to_date_time(time: SystemTime) -> DateTime {
+    const UNIX_EPOCH: Duration = Duration::from_secs(11_644_473_600);
+    let since_1601 = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        + UNIX_EPOCH;
+
+    DateTime {
+        UniversalTime: (since_1601.as_nanos() / 100) as i64,
+    }
+}
+
+fn notification_data(
+    values: Vec<(&str, String)>,
+    sequence_number: u32,
+) -> windows::core::Result<NotificationData> {
+    let data = NotificationData::new()?;
+    let map = data.Values()?;
+    for (key, value) in values {
+        map.Insert(key, value)?;
+    }
+    data.SetSequenceNumber(sequence_number)?;
+    Ok(data)
+}
+
+fn reply_text(args: &ToastActivatedEventArgs) -> windows::core::Result<String> {
+    let value = args.UserInput()?.Lookup(REPLY_INPUT)?;
+    Ok(value.cast::<IPropertyValue>()?.GetString()?.to_string())
+}
@@ -0,0 +1,74 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{collections::HashMap, path::PathBuf};
+
+use scopeguard::defer;
+use widestring::WideCStr;
+use windows::{
+    core::GUID,
+    Win32::{
+        Foundation::HANDLE,
+        System::Com::CoTaskMemFree,
+        UI::Shell::{
+            FOLDERID_Desktop, FOLDERID_Documents, FOLDERID_Downloads, FOLDERID_Music,
+            FOLDERID_Pictures, FOLDERID_Public, FOLDERID_Templates, FOLDERID_Videos,
+            SHGetKnownFolderPath,
+        },
+    },
+};
+use zbus::Connection;
+
+use super::wslpath;
+use crate::proxies::userdirs::UserDirsProxy;
+
+/// The known folders that have an xdg-user-dirs equivalent.
+const KNOWN_FOLDERS: &[(&str, GUID)] = &[
+    ("DESKTOP", FOLDERID_Desktop),
+    ("DOWNLOAD", FOLDERID_Downloads),
+    ("TEMPLATES", FOLDERID_Templates),
+    ("PUBLICSHARE", FOLDERID_Public),
+    ("DOCUMENTS", FOLDERID_Documents),
+    ("MUSIC", FOLDERID_Music),
+    ("PICTURES", FOLDERID_Pictures),
+    ("VIDEOS", FOLDERID_Videos),
+];
+
+fn get_known_folder(id: &GUID) -> windows::core::Result<PathBuf> {
+    unsafe {
+        let path_raw = SHGetKnownFolderPath(id, 0, HANDLE::default())?;
+        defer! { CoTaskMemFree(path_raw.0 as _) };
+        Ok(PathBuf::from(
+            // SAFETY: to_os_string() makes a copy of the string, so it is safe to free it afterwards
+            WideCStr::from_ptr_str(path_raw.0).to_os_string(),
+        ))
+    }
+}
+
+/// Sends the WSL paths of the known folders to the bridge, such that it can sync the xdg-user-dirs.
+pub async fn send_to_bridge(connection: &Connection) -> anyhow::Result<()> {
+    let mut folders = HashMap::new();
+
+    for (name, id) in KNOWN_FOLDERS {
+        // folders on network shares and other odd places can't be mapped into WSL.
+        match get_known_folder(id)
+            .map_err(anyhow::Error::from)
+            .and_then(|path| wslpath::to_wsl(&path))
+        {
+            Ok(path) => {
+                folders.insert(*name, path);
+            }
+            Err(e) => log::warn!("skipping known folder {}: {}", name, e),
+        }
+    }
+
+    let folders = folders.iter().map(|(k, v)| (*k, v.as_str())).collect();
+    UserDirsProxy::new(connection)
+        .await?
+        .set_known_folders(folders)
+        .await?;
+
+    Ok(())
+}
@@ -5,10 +5,14 @@
 
 use windows::Win32::Foundation::{ERROR_SUCCESS, WIN32_ERROR};
 
+pub mod knownfolders;
+pub mod remotefiles;
 pub mod vmcompute;
 pub mod vmsocket;
 pub mod wslpath;
 
+pub use backend_core::cache;
+
 #[macro_export]
 macro_rules! unwrap_or_log {
     ( $e:expr ) => {
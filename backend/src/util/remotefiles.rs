@@ -0,0 +1,118 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::bail;
+use zbus::Connection;
+
+use super::{cache::Cache, wslpath};
+use crate::proxies::files::FilesProxy;
+
+/// Files larger than this are not fetched.
+const MAX_FETCH_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The size of each read request sent to the bridge.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+struct CachedFile {
+    size: u64,
+    modified: u64,
+    path: PathBuf,
+}
+
+/// Fetches files from the distro through the bridge, and stores them in the cache.
+pub struct RemoteFiles {
+    proxy: FilesProxy<'static>,
+    fetched: Mutex<HashMap<String, CachedFile>>,
+}
+
+impl RemoteFiles {
+    pub async fn new(connection: &Connection) -> zbus::Result<Self> {
+        Ok(Self {
+            proxy: FilesProxy::new(connection).await?,
+            fetched: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns a path that Windows can use to access the file.
+    pub async fn fetch(&self, wsl_path: &str) -> anyhow::Result<PathBuf> {
+        self.fetch_file(wsl_path, None).await
+    }
+
+    /// Fetches a file like `fetch`, and keeps it in the cache until `owner` releases it.
+    pub async fn fetch_owned(&self, wsl_path: &str, owner: &str) -> anyhow::Result<PathBuf> {
+        self.fetch_file(wsl_path, Some(owner)).await
+    }
+
+    async fn fetch_file(&self, wsl_path: &str, owner: Option<&str>) -> anyhow::Result<PathBuf> {
+        let win_path = wslpath::to_windows(wsl_path);
+
+        // files on Windows drives can be used directly.
+        if !wslpath::is_distro_path(&win_path) {
+            return Ok(win_path);
+        }
+
+        let (size, modified) = self.proxy.stat(wsl_path).await?;
+        if size > MAX_FETCH_SIZE {
+            bail!("{} is too large to fetch ({} bytes)", wsl_path, size);
+        }
+
+        if let Some(cached) = self.fetched.lock().unwrap().get(wsl_path) {
+            if cached.size == size && cached.modified == modified {
+                // touching the file keeps it from being evicted, and fails if it already was.
+                let kept = match owner {
+                    Some(owner) => Cache::global().own(&cached.path, owner),
+                    None => Cache::global().touch(&cached.path),
+                };
+                if kept.is_ok() {
+                    return Ok(cached.path.clone());
+                }
+            }
+        }
+
+        log::debug!("fetching {} ({} bytes)", wsl_path, size);
+
+        let mut data = Vec::with_capacity(size as _);
+        while (data.len() as u64) < size {
+            let chunk = self
+                .proxy
+                .read(wsl_path, data.len() as _, CHUNK_SIZE)
+                .await?;
+            if chunk.is_empty() {
+                // the file was truncated while we were reading it.
+                break;
+            }
+            data.extend(chunk);
+        }
+
+        let extension = Path::new(wsl_path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let owner = owner.map(str::to_string);
+        let path = tokio::task::spawn_blocking(move || match owner {
+            Some(owner) => Cache::global().insert_owned(&owner, &data, &extension),
+            None => Cache::global().insert(&data, &extension),
+        })
+        .await??;
+
+        self.fetched.lock().unwrap().insert(
+            wsl_path.to_string(),
+            CachedFile {
+                size,
+                modified,
+                path: path.clone(),
+            },
+        );
+
+        Ok(path)
+    }
+}
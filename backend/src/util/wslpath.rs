@@ -82,29 +82,11 @@ pub fn to_wsl(win_path: &Path) -> anyhow::Result<String> {
     Ok(wsl_path)
 }
 
-pub fn get_temp_copy(wsl_file_path: &str) -> std::io::Result<PathBuf> {
-    let win_src_path = to_windows(wsl_file_path);
-
+/// Returns true if the path refers to a file inside the distro, rather than on a Windows drive.
+pub fn is_distro_path(win_path: &Path) -> bool {
     let mut network_share = PathBuf::from(WSL_DOMAIN);
     network_share.push(Config::global().distro_name());
-
-    if !win_src_path.starts_with(&network_share) {
-        return Ok(win_src_path);
-    }
-
-    let mut win_dest_path = std::env::temp_dir();
-    win_dest_path.push("Wormhole");
-    win_dest_path.push(win_src_path.strip_prefix(&network_share).unwrap());
-
-    let src_metadata = std::fs::metadata(&win_src_path)?;
-    let dest_metadata = std::fs::metadata(&win_dest_path).ok();
-
-    if dest_metadata.is_none() || src_metadata.modified()? > dest_metadata.unwrap().modified()? {
-        std::fs::create_dir_all(win_dest_path.parent().unwrap())?;
-        std::fs::copy(&win_src_path, &win_dest_path)?;
-    }
-
-    Ok(win_dest_path)
+    win_path.starts_with(&network_share)
 }
 
 struct LogicalDrives(u32);
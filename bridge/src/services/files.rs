@@ -0,0 +1,339 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use zbus::{dbus_interface, fdo, Connection};
+
+/// The largest file that can be read through the service.
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The largest chunk that can be returned by a single call to `Read`.
+const MAX_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Colon separated list of directories that replaces the default allowed roots.
+const ALLOWED_ROOTS_VAR: &str = "WORMHOLE_ALLOWED_ROOTS";
+
+/// Gives the backend read access to files inside the distro.
+///
+/// Unix file descriptors cannot be passed across the VM boundary, so files are read in chunks instead.
+pub struct Files {
+    policy: Policy,
+}
+
+impl Files {
+    pub async fn init(connection: &Connection) -> zbus::Result<()> {
+        connection.request_name("com.github.raytar.Files").await?;
+
+        let policy = Policy::from_env();
+        log::debug!("allowed roots: {:?}", policy.roots);
+
+        connection
+            .object_server_mut()
+            .await
+            .at("/com/github/raytar/Files", Files { policy })?;
+
+        Ok(())
+    }
+}
+
+#[dbus_interface(name = "com.github.raytar.Files")]
+impl Files {
+    /// Returns the size of the file and its modification time in seconds since the epoch.
+    async fn stat(&self, path: &str) -> fdo::Result<(u64, u64)> {
+        let path = self.policy.check(path)?;
+
+        tokio::task::spawn_blocking(move || {
+            let file = File::open(&path)?;
+            let metadata = Policy::check_metadata(&file)?;
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            Ok((metadata.len(), modified))
+        })
+        .await
+        .map_err(|e| fdo::Error::Failed(e.to_string()))?
+        .map_err(to_fdo_error)
+    }
+
+    /// Reads at most `length` bytes starting at `offset`.
+    /// An empty result means that the end of the file has been reached.
+    async fn read(&self, path: &str, offset: u64, length: u64) -> fdo::Result<Vec<u8>> {
+        let path = self.policy.check(path)?;
+
+        if length > MAX_CHUNK_SIZE {
+            return Err(fdo::Error::LimitsExceeded(format!(
+                "chunks are limited to {} bytes",
+                MAX_CHUNK_SIZE
+            )));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let mut file = File::open(&path)?;
+            Policy::check_metadata(&file)?;
+
+            let mut buffer = Vec::with_capacity(length as _);
+            file.seek(SeekFrom::Start(offset))?;
+            file.take(length).read_to_end(&mut buffer)?;
+            Ok(buffer)
+        })
+        .await
+        .map_err(|e| fdo::Error::Failed(e.to_string()))?
+        .map_err(to_fdo_error)
+    }
+
+    /// Returns the path of the desktop entry with the given id, such as `org.gnome.Calculator`.
+    fn find_desktop_entry(&self, desktop_entry: &str) -> fdo::Result<String> {
+        if desktop_entry.is_empty()
+            || desktop_entry.starts_with('.')
+            || desktop_entry.contains(['/', '\0'])
+        {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "invalid desktop entry: {}",
+                desktop_entry
+            )));
+        }
+
+        desktop_entry_paths(&data_dirs(), desktop_entry)
+            .into_iter()
+            .filter(|path| path.is_file())
+            .find_map(|path| self.policy.check(&path.to_string_lossy()).ok())
+            .map(|path| path.to_string_lossy().into_owned())
+            .ok_or_else(|| {
+                fdo::Error::FileNotFound(format!("no desktop entry for {}", desktop_entry))
+            })
+    }
+}
+
+/// Returns the XDG data directories, most important first.
+fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    dirs.extend(xdg_home("XDG_DATA_HOME", ".local/share"));
+
+    match std::env::var_os("XDG_DATA_DIRS") {
+        Some(data_dirs) if !data_dirs.is_empty() => dirs.extend(std::env::split_paths(&data_dirs)),
+        _ => dirs.extend([
+            PathBuf::from("/usr/local/share"),
+            PathBuf::from("/usr/share"),
+        ]),
+    }
+
+    dirs
+}
+
+/// Returns the directory in the environment variable, or its default under `$HOME`.
+fn xdg_home(var: &str, default: &str) -> Option<PathBuf> {
+    match std::env::var_os(var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(Path::new(&std::env::var_os("HOME")?).join(default)),
+    }
+}
+
+/// Returns where the desktop entry with the given id may be found, in order.
+///
+/// Entries in subdirectories have ids where the slashes are replaced by dashes, so
+/// `kde-konsole` may also be `kde/konsole.desktop`.
+fn desktop_entry_paths(data_dirs: &[PathBuf], desktop_entry: &str) -> Vec<PathBuf> {
+    let mut names = vec![format!("{}.desktop", desktop_entry)];
+    for (i, _) in desktop_entry.match_indices('-') {
+        let (dir, name) = desktop_entry.split_at(i);
+        names.push(format!("{}/{}.desktop", dir.replace('-', "/"), &name[1..]));
+    }
+
+    data_dirs
+        .iter()
+        .flat_map(|dir| {
+            names
+                .iter()
+                .map(move |name| dir.join("applications").join(name))
+        })
+        .collect()
+}
+
+struct Policy {
+    roots: Vec<PathBuf>,
+}
+
+impl Policy {
+    fn from_env() -> Self {
+        let roots: Vec<PathBuf> = match std::env::var_os(ALLOWED_ROOTS_VAR) {
+            Some(roots) => std::env::split_paths(&roots).collect(),
+            None => {
+                let mut roots = vec![
+                    PathBuf::from("/usr/share"),
+                    PathBuf::from("/usr/local/share"),
+                    PathBuf::from("/var/lib/flatpak/exports/share"),
+                    PathBuf::from("/tmp"),
+                ];
+                // the icons and applications of the user, but not the rest of their home
+                // directory, which has keys and other secrets.
+                roots.extend(xdg_home("XDG_DATA_HOME", ".local/share"));
+                roots.extend(xdg_home("XDG_CACHE_HOME", ".cache"));
+                roots
+            }
+        };
+
+        Self::new(&roots)
+    }
+
+    fn new(roots: &[PathBuf]) -> Self {
+        Self {
+            // roots must be canonical for the prefix check to work.
+            roots: roots
+                .iter()
+                .filter_map(|root| fs::canonicalize(root).ok())
+                .collect(),
+        }
+    }
+
+    /// Resolves the path and makes sure that it is inside one of the allowed roots.
+    fn check(&self, path: &str) -> fdo::Result<PathBuf> {
+        let path = Path::new(path);
+        if !path.is_absolute() {
+            return Err(fdo::Error::InvalidArgs(String::from(
+                "path must be absolute",
+            )));
+        }
+
+        // resolve symlinks and '..' before comparing against the allowed roots.
+        let path = fs::canonicalize(path).map_err(to_fdo_error)?;
+
+        if self.roots.iter().any(|root| path.starts_with(root)) {
+            Ok(path)
+        } else {
+            log::debug!("denied access to {}", path.display());
+            Err(fdo::Error::AccessDenied(format!(
+                "{} is outside of the allowed roots",
+                path.display()
+            )))
+        }
+    }
+
+    fn check_metadata(file: &File) -> io::Result<fs::Metadata> {
+        let metadata = file.metadata()?;
+
+        if !metadata.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a regular file",
+            ));
+        }
+
+        if metadata.len() > MAX_FILE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("file is larger than {} bytes", MAX_FILE_SIZE),
+            ));
+        }
+
+        Ok(metadata)
+    }
+}
+
+fn to_fdo_error(e: io::Error) -> fdo::Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => fdo::Error::FileNotFound(e.to_string()),
+        io::ErrorKind::PermissionDenied => fdo::Error::AccessDenied(e.to_string()),
+        io::ErrorKind::InvalidInput => fdo::Error::InvalidArgs(e.to_string()),
+        io::ErrorKind::InvalidData => fdo::Error::LimitsExceeded(e.to_string()),
+        _ => fdo::Error::IOError(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("share");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("icon.png"), "icon").unwrap();
+        fs::write(dir.path().join("id_rsa"), "secret").unwrap();
+        let policy = Policy::new(std::slice::from_ref(&root));
+
+        let icon = fs::canonicalize(root.join("icon.png")).unwrap();
+        assert_eq!(policy.check(icon.to_str().unwrap()).unwrap(), icon);
+
+        assert!(matches!(
+            policy.check("share/icon.png"),
+            Err(fdo::Error::InvalidArgs(_))
+        ));
+        assert!(matches!(
+            policy.check(root.join("missing.png").to_str().unwrap()),
+            Err(fdo::Error::FileNotFound(_))
+        ));
+        assert!(matches!(
+            policy.check(root.join("../id_rsa").to_str().unwrap()),
+            Err(fdo::Error::AccessDenied(_))
+        ));
+
+        // symlinks are followed before the check.
+        symlink(dir.path().join("id_rsa"), root.join("escape")).unwrap();
+        assert!(matches!(
+            policy.check(root.join("escape").to_str().unwrap()),
+            Err(fdo::Error::AccessDenied(_))
+        ));
+        symlink(root.join("icon.png"), root.join("link.png")).unwrap();
+        assert_eq!(
+            policy
+                .check(root.join("link.png").to_str().unwrap())
+                .unwrap(),
+            icon
+        );
+    }
+
+    #[test]
+    fn test_check_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let err = Policy::check_metadata(&File::open(dir.path()).unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let path = dir.path().join("large");
+        let file = File::create(&path).unwrap();
+        file.set_len(MAX_FILE_SIZE).unwrap();
+        assert!(Policy::check_metadata(&File::open(&path).unwrap()).is_ok());
+
+        file.set_len(MAX_FILE_SIZE + 1).unwrap();
+        let err = Policy::check_metadata(&File::open(&path).unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_desktop_entry_paths() {
+        let dirs = [
+            PathBuf::from("/home/user/.local/share"),
+            PathBuf::from("/usr/share"),
+        ];
+
+        assert_eq!(
+            desktop_entry_paths(&dirs, "org.gnome.Calculator"),
+            [
+                PathBuf::from("/home/user/.local/share/applications/org.gnome.Calculator.desktop"),
+                PathBuf::from("/usr/share/applications/org.gnome.Calculator.desktop"),
+            ]
+        );
+        assert_eq!(
+            desktop_entry_paths(&dirs[1..], "kde-org-konsole"),
+            [
+                PathBuf::from("/usr/share/applications/kde-org-konsole.desktop"),
+                PathBuf::from("/usr/share/applications/kde/org-konsole.desktop"),
+                PathBuf::from("/usr/share/applications/kde/org/konsole.desktop"),
+            ]
+        );
+    }
+}
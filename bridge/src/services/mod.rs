@@ -5,11 +5,15 @@
 
 use zbus::Connection;
 
+pub mod files;
 pub mod icons;
+pub mod userdirs;
 pub mod wsl;
 
 pub async fn init_all(connection: &Connection) -> zbus::Result<()> {
+    files::Files::init(connection).await?;
     icons::Icons::init(connection).await?;
+    userdirs::UserDirs::init(connection).await?;
     wsl::WSL::init(connection).await?;
 
     Ok(())
@@ -0,0 +1,574 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use zbus::{dbus_interface, fdo, Connection};
+use zvariant::OwnedValue;
+
+/// The directories managed by xdg-user-dirs, and their default names in the home directory.
+const USER_DIRS: &[(&str, &str)] = &[
+    ("DESKTOP", "Desktop"),
+    ("DOWNLOAD", "Downloads"),
+    ("TEMPLATES", "Templates"),
+    ("PUBLICSHARE", "Public"),
+    ("DOCUMENTS", "Documents"),
+    ("MUSIC", "Music"),
+    ("PICTURES", "Pictures"),
+    ("VIDEOS", "Videos"),
+];
+
+const HEADER: &str = "# This file is written by Wormhole from the Windows known folders.
+# Local changes are backed up before the file is written again.
+";
+
+const CHECKSUM_PREFIX: &str = "# checksum: ";
+
+/// Points the xdg-user-dirs at the Windows known folders.
+pub struct UserDirs {
+    /// The Linux paths of the Windows known folders, keyed by their xdg-user-dirs name.
+    known_folders: Mutex<HashMap<String, String>>,
+}
+
+impl UserDirs {
+    pub async fn init(connection: &Connection) -> zbus::Result<()> {
+        connection
+            .request_name("com.github.raytar.UserDirs")
+            .await?;
+
+        connection.object_server_mut().await.at(
+            "/com/github/raytar/UserDirs",
+            UserDirs {
+                known_folders: Mutex::new(HashMap::new()),
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+#[dbus_interface(name = "com.github.raytar.UserDirs")]
+impl UserDirs {
+    /// Called by the backend when it connects.
+    fn set_known_folders(&self, folders: HashMap<String, String>) -> fdo::Result<()> {
+        for (name, path) in &folders {
+            if !USER_DIRS.iter().any(|(n, _)| n == name) {
+                return Err(fdo::Error::InvalidArgs(format!("unknown folder: {}", name)));
+            }
+            if !Path::new(path).is_absolute() {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "path is not absolute: {}",
+                    path
+                )));
+            }
+        }
+
+        log::debug!("known folders: {:?}", folders);
+        *self.known_folders.lock().unwrap() = folders;
+        Ok(())
+    }
+
+    /// Writes `~/.config/user-dirs.dirs`, and returns a description of each change.
+    ///
+    /// Options:
+    /// - `dry-run` (b): only describe the changes.
+    /// - `symlinks` (b): replace the directories in the home directory with symlinks instead.
+    fn sync(&self, options: HashMap<String, OwnedValue>) -> fdo::Result<Vec<String>> {
+        let dry_run = get_bool_option(&options, "dry-run")?;
+        let symlinks = get_bool_option(&options, "symlinks")?;
+
+        let folders = self.known_folders.lock().unwrap().clone();
+        if folders.is_empty() {
+            return Err(fdo::Error::Failed(String::from(
+                "the known folders have not been received from the backend",
+            )));
+        }
+
+        let home = std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .ok_or_else(|| fdo::Error::Failed(String::from("HOME is not set")))?;
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".config"));
+
+        let file_path = config_home.join("user-dirs.dirs");
+        let existing = match fs::read_to_string(&file_path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(fdo::Error::IOError(e.to_string())),
+        };
+
+        let actions = plan(&home, &file_path, existing.as_deref(), &folders, symlinks);
+
+        if !dry_run {
+            for action in &actions {
+                log::info!("{}", action);
+                action
+                    .apply()
+                    .map_err(|e| fdo::Error::IOError(format!("{}: {}", action, e)))?;
+            }
+        }
+
+        Ok(actions.iter().map(|a| a.to_string()).collect())
+    }
+}
+
+fn get_bool_option(options: &HashMap<String, OwnedValue>, name: &str) -> fdo::Result<bool> {
+    match options.get(name) {
+        Some(value) => bool::try_from(value.clone())
+            .map_err(|_| fdo::Error::InvalidArgs(format!("{} must be a boolean", name))),
+        None => Ok(false),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Backup { from: PathBuf, to: PathBuf },
+    Write { path: PathBuf, contents: String },
+    RemoveEmptyDir(PathBuf),
+    Symlink { link: PathBuf, target: PathBuf },
+    Skip(String),
+}
+
+impl Action {
+    fn apply(&self) -> io::Result<()> {
+        match self {
+            Action::Backup { from, to } => fs::copy(from, to).map(|_| ()),
+            Action::Write { path, contents } => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(path, contents)
+            }
+            Action::RemoveEmptyDir(path) => fs::remove_dir(path),
+            Action::Symlink { link, target } => std::os::unix::fs::symlink(target, link),
+            Action::Skip(_) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Backup { from, to } => {
+                write!(f, "back up {} to {}", from.display(), to.display())
+            }
+            Action::Write { path, .. } => write!(f, "write {}", path.display()),
+            Action::RemoveEmptyDir(path) => write!(f, "remove empty directory {}", path.display()),
+            Action::Symlink { link, target } => {
+                write!(f, "link {} to {}", link.display(), target.display())
+            }
+            Action::Skip(reason) => write!(f, "skip: {}", reason),
+        }
+    }
+}
+
+/// Works out which changes are needed to point the user dirs at the known folders.
+fn plan(
+    home: &Path,
+    file_path: &Path,
+    existing: Option<&str>,
+    folders: &HashMap<String, String>,
+    symlinks: bool,
+) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let mut entries = existing.map(parse).unwrap_or_default();
+
+    for (name, default_dir) in USER_DIRS {
+        let target = match folders.get(*name) {
+            Some(target) => PathBuf::from(target),
+            None => continue,
+        };
+
+        let value = if symlinks {
+            // keep the name of the directory that is currently in use.
+            let dir_name = match get_entry(&entries, name) {
+                Some(value) => value
+                    .strip_prefix("$HOME/")
+                    .unwrap_or(default_dir)
+                    .to_string(),
+                None => default_dir.to_string(),
+            };
+            let link = home.join(&dir_name);
+
+            if plan_symlink(&mut actions, &link, &target) {
+                format!("$HOME/{}", dir_name)
+            } else {
+                target.to_string_lossy().to_string()
+            }
+        } else {
+            target.to_string_lossy().to_string()
+        };
+
+        set_entry(&mut entries, name, &value);
+    }
+
+    let contents = render(&entries);
+
+    match existing {
+        Some(existing) if body(existing) == body(&contents) => {}
+        Some(existing) => {
+            if is_customized(existing) {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                actions.insert(
+                    0,
+                    Action::Backup {
+                        from: file_path.to_owned(),
+                        to: file_path.with_extension(format!("dirs.{}.bak", timestamp)),
+                    },
+                );
+            }
+            actions.push(Action::Write {
+                path: file_path.to_owned(),
+                contents,
+            });
+        }
+        None => actions.push(Action::Write {
+            path: file_path.to_owned(),
+            contents,
+        }),
+    }
+
+    actions
+}
+
+/// Returns false if the directory could not be replaced by a symlink.
+fn plan_symlink(actions: &mut Vec<Action>, link: &Path, target: &Path) -> bool {
+    match fs::symlink_metadata(link) {
+        Err(_) => {}
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            if fs::read_link(link).ok().as_deref() == Some(target) {
+                return true;
+            }
+            // the symlink was made by the user, and can't be backed up like a file.
+            actions.push(Action::Skip(format!(
+                "{} is a symlink to somewhere else, using {} directly",
+                link.display(),
+                target.display()
+            )));
+            return false;
+        }
+        Ok(metadata) if metadata.is_dir() => {
+            let is_empty = fs::read_dir(link)
+                .map(|mut entries| entries.next().is_none())
+                .unwrap_or(false);
+            if !is_empty {
+                actions.push(Action::Skip(format!(
+                    "{} is not empty, using {} directly",
+                    link.display(),
+                    target.display()
+                )));
+                return false;
+            }
+            actions.push(Action::RemoveEmptyDir(link.to_owned()));
+        }
+        Ok(_) => {
+            actions.push(Action::Skip(format!(
+                "{} is not a directory, using {} directly",
+                link.display(),
+                target.display()
+            )));
+            return false;
+        }
+    }
+
+    actions.push(Action::Symlink {
+        link: link.to_owned(),
+        target: target.to_owned(),
+    });
+    true
+}
+
+/// A line in user-dirs.dirs. Lines that are not entries are kept as they are.
+#[derive(Debug, PartialEq)]
+enum Line {
+    Entry { name: String, value: String },
+    Other(String),
+}
+
+fn parse(contents: &str) -> Vec<Line> {
+    contents
+        .lines()
+        // our own header is written again by render().
+        .filter(|line| !HEADER.lines().any(|h| h == *line) && !line.starts_with(CHECKSUM_PREFIX))
+        .map(|line| parse_entry(line).unwrap_or_else(|| Line::Other(line.to_string())))
+        .collect()
+}
+
+fn parse_entry(line: &str) -> Option<Line> {
+    let (key, value) = line.trim().split_once('=')?;
+    let name = key.strip_prefix("XDG_")?.strip_suffix("_DIR")?;
+    let value = value.strip_prefix('"')?.strip_suffix('"')?;
+
+    Some(Line::Entry {
+        name: name.to_string(),
+        value: unescape(value),
+    })
+}
+
+fn get_entry<'a>(lines: &'a [Line], name: &str) -> Option<&'a str> {
+    lines.iter().find_map(|line| match line {
+        Line::Entry { name: n, value } if n == name => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+fn set_entry(lines: &mut Vec<Line>, name: &str, new_value: &str) {
+    for line in lines.iter_mut() {
+        if let Line::Entry { name: n, value } = line {
+            if n == name {
+                *value = new_value.to_string();
+                return;
+            }
+        }
+    }
+    lines.push(Line::Entry {
+        name: name.to_string(),
+        value: new_value.to_string(),
+    });
+}
+
+fn render(lines: &[Line]) -> String {
+    let mut body = String::new();
+    for line in lines {
+        match line {
+            Line::Entry { name, value } => {
+                body.push_str(&format!("XDG_{}_DIR=\"{}\"\n", name, escape(value)))
+            }
+            Line::Other(other) => {
+                body.push_str(other);
+                body.push('\n');
+            }
+        }
+    }
+
+    format!(
+        "{}{}{:016x}\n{}",
+        HEADER,
+        CHECKSUM_PREFIX,
+        checksum(&body),
+        body
+    )
+}
+
+/// Returns the file contents without our header.
+fn body(contents: &str) -> &str {
+    match contents.find(CHECKSUM_PREFIX) {
+        Some(start) if contents.starts_with(HEADER) => contents[start..]
+            .split_once('\n')
+            .map(|(_, body)| body)
+            .unwrap_or_default(),
+        _ => contents,
+    }
+}
+
+/// A file is customized unless we wrote it and it has not been changed since.
+fn is_customized(contents: &str) -> bool {
+    if !contents.starts_with(HEADER) {
+        return true;
+    }
+
+    let expected = contents[HEADER.len()..]
+        .strip_prefix(CHECKSUM_PREFIX)
+        .and_then(|rest| rest.split_once('\n'))
+        .and_then(|(checksum, _)| u64::from_str_radix(checksum, 16).ok());
+
+    expected != Some(checksum(body(contents)))
+}
+
+/// FNV-1a, which is stable across Rust versions unlike the standard library hasher.
+fn checksum(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The values are shell-escaped, as they are meant to be sourced by a shell.
+fn escape(value: &str) -> String {
+    // a leading $HOME must be expanded by the shell.
+    let (prefix, rest) = match value.strip_prefix("$HOME") {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => ("$HOME", rest),
+        _ => ("", value),
+    };
+
+    let mut escaped = String::from(prefix);
+    for c in rest.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                unescaped.push(next);
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XDG_DEFAULT: &str = r#"# This file is written by xdg-user-dirs-update
+# If you want to change or add directories, just edit the line you're
+# interested in. All local changes will be retained on the next run.
+XDG_DESKTOP_DIR="$HOME/Desktop"
+XDG_DOWNLOAD_DIR="$HOME/Downloads"
+XDG_PROJECTS_DIR="$HOME/src"
+"#;
+
+    fn folders() -> HashMap<String, String> {
+        let mut folders = HashMap::new();
+        folders.insert(
+            String::from("DOWNLOAD"),
+            String::from("/mnt/c/Users/admin/Downloads"),
+        );
+        folders.insert(
+            String::from("DOCUMENTS"),
+            String::from("/mnt/c/Users/admin/My \"Documents\""),
+        );
+        folders
+    }
+
+    fn written_contents(actions: &[Action]) -> &str {
+        actions
+            .iter()
+            .find_map(|a| match a {
+                Action::Write { contents, .. } => Some(contents.as_str()),
+                _ => None,
+            })
+            .expect("no write action")
+    }
+
+    #[test]
+    fn test_parse_and_render() {
+        let lines = parse(XDG_DEFAULT);
+        assert_eq!(get_entry(&lines, "DOWNLOAD"), Some("$HOME/Downloads"));
+        assert_eq!(get_entry(&lines, "PROJECTS"), Some("$HOME/src"));
+        assert_eq!(body(&render(&lines)), XDG_DEFAULT);
+    }
+
+    #[test]
+    fn test_escape_roundtrip() {
+        let value = r#"/mnt/c/Users/a "b" $c \d"#;
+        assert_eq!(escape(value), r#"/mnt/c/Users/a \"b\" \$c \\d"#);
+        assert_eq!(unescape(&escape(value)), value);
+        assert_eq!(escape("$HOME/Downloads"), "$HOME/Downloads");
+    }
+
+    #[test]
+    fn test_plan_backs_up_customized_file() {
+        let actions = plan(
+            Path::new("/nonexistent"),
+            Path::new("/nonexistent/.config/user-dirs.dirs"),
+            Some(XDG_DEFAULT),
+            &folders(),
+            false,
+        );
+
+        assert!(matches!(actions[0], Action::Backup { .. }));
+
+        let contents = written_contents(&actions);
+        assert!(contents.starts_with(HEADER));
+        assert!(contents.contains(r#"XDG_DOWNLOAD_DIR="/mnt/c/Users/admin/Downloads""#));
+        assert!(contents.contains(r#"XDG_DOCUMENTS_DIR="/mnt/c/Users/admin/My \"Documents\"""#));
+        // entries that are not known folders are kept.
+        assert!(contents.contains(r#"XDG_PROJECTS_DIR="$HOME/src""#));
+    }
+
+    #[test]
+    fn test_plan_overwrites_own_file() {
+        let file_path = Path::new("/nonexistent/.config/user-dirs.dirs");
+        let home = Path::new("/nonexistent");
+        let mut folders = folders();
+
+        let first = plan(home, file_path, None, &folders, false);
+        let written = written_contents(&first).to_string();
+        assert!(!is_customized(&written));
+
+        // nothing changes if the known folders are the same.
+        assert!(plan(home, file_path, Some(&written), &folders, false).is_empty());
+
+        // no backup is needed when the file has not been edited.
+        folders.insert(String::from("MUSIC"), String::from("/mnt/d/Music"));
+        let actions = plan(home, file_path, Some(&written), &folders, false);
+        assert!(matches!(actions[..], [Action::Write { .. }]));
+
+        // but an edited file is backed up.
+        let edited = written.replace("/mnt/c/Users/admin/Downloads", "/data/downloads");
+        assert!(is_customized(&edited));
+        let actions = plan(home, file_path, Some(&edited), &folders, false);
+        assert!(matches!(actions[0], Action::Backup { .. }));
+    }
+
+    #[test]
+    fn test_plan_symlink() {
+        let home = tempfile::tempdir().unwrap();
+        let target = home.path().join("Downloads on Windows");
+        fs::create_dir(&target).unwrap();
+        let plan_link = |name: &str| {
+            let mut actions = Vec::new();
+            let linked = plan_symlink(&mut actions, &home.path().join(name), &target);
+            (linked, actions)
+        };
+
+        // a missing directory is linked.
+        let (linked, actions) = plan_link("Missing");
+        assert!(linked);
+        assert!(matches!(actions[..], [Action::Symlink { .. }]));
+
+        // nothing is done if it is already linked.
+        std::os::unix::fs::symlink(&target, home.path().join("Linked")).unwrap();
+        assert_eq!(plan_link("Linked"), (true, vec![]));
+
+        // symlinks to other directories are left alone.
+        std::os::unix::fs::symlink("/data/downloads", home.path().join("Foreign")).unwrap();
+        let (linked, actions) = plan_link("Foreign");
+        assert!(!linked);
+        assert!(matches!(actions[..], [Action::Skip(_)]));
+
+        // empty directories are replaced.
+        fs::create_dir(home.path().join("Empty")).unwrap();
+        let (linked, actions) = plan_link("Empty");
+        assert!(linked);
+        assert!(matches!(
+            actions[..],
+            [Action::RemoveEmptyDir(_), Action::Symlink { .. }]
+        ));
+
+        // but not directories with files in them.
+        fs::create_dir(home.path().join("Full")).unwrap();
+        fs::write(home.path().join("Full/file.txt"), "").unwrap();
+        let (linked, actions) = plan_link("Full");
+        assert!(!linked);
+        assert!(matches!(actions[..], [Action::Skip(_)]));
+
+        fs::write(home.path().join("File"), "").unwrap();
+        let (linked, actions) = plan_link("File");
+        assert!(!linked);
+        assert!(matches!(actions[..], [Action::Skip(_)]));
+    }
+}
@@ -5,6 +5,7 @@
 
 use zbus::{dbus_interface, fdo, Connection};
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct WSL {}
 
 impl WSL {
@@ -30,8 +30,8 @@ pub fn build_ui(app: &gtk::Application) {
         .margin_end(12)
         .build();
 
-    // box_layout.append(&gnotification::build_ui(app));
-    // box_layout.append(&gtk::Separator::new(gtk::Orientation::Vertical));
+    box_layout.append(&gnotification::build_ui(app));
+    box_layout.append(&gtk::Separator::new(gtk::Orientation::Vertical));
     box_layout.append(&notify_rust::build_ui(app));
 
     window.set_child(Some(&box_layout));
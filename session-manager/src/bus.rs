@@ -0,0 +1,242 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Helpers for finding out whether a D-Bus address can be connected to, and who is listening on it.
+
+use nix::sys::socket::{
+    connect, getsockopt, socket, sockopt::PeerCredentials, AddressFamily, SockAddr, SockFlag,
+    SockType, UnixAddr,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+const TCP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The process that is listening on a bus.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Owner {
+    pub pid: u32,
+    /// The name of the program, like `dbus-daemon` or `dbus-broker`.
+    /// This is `systemd` for socket activated buses, which is how systemd starts dbus-broker.
+    pub program: Option<String>,
+}
+
+enum Connection {
+    Unix(UnixStream),
+    /// The peer of a TCP connection can't be looked up, so the stream is just closed.
+    Tcp,
+}
+
+/// Checks whether any of the transports in the address accepts connections.
+pub fn is_reachable(address: &str) -> bool {
+    open(address).is_some()
+}
+
+/// Returns the process listening on the address, for buses that listen on a unix socket.
+pub fn owner(address: &str) -> Option<Owner> {
+    let stream = match open(address)? {
+        Connection::Unix(stream) => stream,
+        Connection::Tcp => return None,
+    };
+
+    let pid = getsockopt(stream.as_raw_fd(), PeerCredentials).ok()?.pid() as u32;
+    let program = fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|comm| comm.trim_end().to_string());
+
+    Some(Owner { pid, program })
+}
+
+fn open(address: &str) -> Option<Connection> {
+    parse(address).into_iter().find_map(|(transport, params)| {
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.as_str())
+        };
+
+        match transport.as_str() {
+            "unix" => {
+                let addr = if let Some(path) = param("path") {
+                    UnixAddr::new(path).ok()?
+                } else if let Some(name) = param("abstract") {
+                    UnixAddr::new_abstract(name.as_bytes()).ok()?
+                } else {
+                    return None;
+                };
+                connect_unix(&addr).map(Connection::Unix)
+            }
+            "tcp" => {
+                let host = param("host").unwrap_or("localhost");
+                let port: u16 = param("port")?.parse().ok()?;
+                (host, port)
+                    .to_socket_addrs()
+                    .ok()?
+                    .find_map(|addr| TcpStream::connect_timeout(&addr, TCP_TIMEOUT).ok())
+                    .map(|_| Connection::Tcp)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Connects to a unix socket, which unlike `UnixStream::connect` also works for abstract sockets.
+fn connect_unix(addr: &UnixAddr) -> Option<UnixStream> {
+    let fd = socket(
+        AddressFamily::Unix,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .ok()?;
+    // take ownership of the fd, so that it is closed.
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+    connect(fd, &SockAddr::Unix(*addr)).ok()?;
+    Some(stream)
+}
+
+/// Splits a D-Bus address into its transports and their parameters.
+///
+/// Addresses look like `unix:path=/run/user/1000/bus,guid=...;tcp:host=localhost,port=1234`.
+fn parse(address: &str) -> Vec<(String, Vec<(String, String)>)> {
+    address
+        .split(';')
+        .filter_map(|transport| {
+            let (name, params) = transport.split_once(':')?;
+            let params = params
+                .split(',')
+                .filter(|param| !param.is_empty())
+                .filter_map(|param| {
+                    let (key, value) = param.split_once('=')?;
+                    Some((key.to_string(), unescape(value)?))
+                })
+                .collect();
+            Some((name.to_string(), params))
+        })
+        .collect()
+}
+
+/// Returns the address of a bus that listens on `path`.
+pub fn unix_address(path: &Path) -> String {
+    format!("unix:path={}", escape(&path.to_string_lossy()))
+}
+
+/// Escapes a value in a D-Bus address, where only a few characters are allowed unescaped.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_/.\\*".contains(&byte) {
+            escaped.push(byte as char);
+        } else {
+            let _ = write!(escaped, "%{:02x}", byte);
+        }
+    }
+    escaped
+}
+
+/// Decodes the `%xx` escapes that D-Bus uses for bytes that are not allowed in addresses.
+fn unescape(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::process;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("unix:path=/run/user/1000/bus,guid=abc;tcp:host=localhost,port=1234"),
+            [
+                (
+                    String::from("unix"),
+                    vec![
+                        (String::from("path"), String::from("/run/user/1000/bus")),
+                        (String::from("guid"), String::from("abc")),
+                    ]
+                ),
+                (
+                    String::from("tcp"),
+                    vec![
+                        (String::from("host"), String::from("localhost")),
+                        (String::from("port"), String::from("1234")),
+                    ]
+                ),
+            ]
+        );
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("/tmp/a%20b%3b").as_deref(), Some("/tmp/a b;"));
+        assert_eq!(unescape("%2"), None);
+        assert_eq!(unescape("%zz"), None);
+
+        assert_eq!(
+            unix_address(Path::new("/tmp/a b;c")),
+            "unix:path=/tmp/a%20b%3bc"
+        );
+        assert_eq!(unescape(&escape("/tmp/å,=")).as_deref(), Some("/tmp/å,="));
+    }
+
+    #[test]
+    fn test_reachable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("bus");
+        let address = format!("unix:path={}", path.display());
+
+        assert!(!is_reachable(&address));
+        assert_eq!(owner(&address), None);
+
+        let _listener = UnixListener::bind(&path).unwrap();
+        assert!(is_reachable(&address));
+        assert!(is_reachable(&format!("unix:path=/nonexistent;{}", address)));
+        assert_eq!(owner(&address).unwrap().pid, process::id());
+
+        assert!(!is_reachable("unix:tmpdir=/tmp"));
+        assert!(!is_reachable("garbage"));
+    }
+
+    #[test]
+    fn test_abstract() {
+        let name = format!("wsl-session-manager-test-{}", process::id());
+        let address = format!("unix:abstract={}", name);
+        assert!(!is_reachable(&address));
+
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .unwrap();
+        let _listener = unsafe { UnixListener::from_raw_fd(fd) };
+        let addr = UnixAddr::new_abstract(name.as_bytes()).unwrap();
+        nix::sys::socket::bind(fd, &SockAddr::Unix(addr)).unwrap();
+        nix::sys::socket::listen(fd, 1).unwrap();
+
+        assert!(is_reachable(&address));
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Generates the configuration for the dbus-daemon that the session manager starts.
+//!
+//! The configuration is the same as the stock session configuration, except that it listens in
+//! the runtime dir, and that it adds a service dir which allows the bridge to be started by D-Bus
+//! activation the first time one of its names is used.
+
+use anyhow::{Context, Result};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bus;
+
+/// The name of the bridge executable.
+const BRIDGE: &str = "xdp-wsl-bridge";
+
+/// The names that are owned by the bridge.
+const BRIDGE_NAMES: &[&str] = &[
+    "org.freedesktop.impl.portal.desktop.wsl",
+    "com.github.raytar.Icons",
+    "com.github.raytar.WSL",
+    "com.github.raytar.Files",
+    "com.github.raytar.UserDirs",
+];
+
+/// Writes the configuration to `dir`, and returns the path of the config file.
+///
+/// `extra_policy` is included at the end of the configuration if it exists, which allows the
+/// user to add their own rules.
+pub fn write(dir: &Path, bus_socket: &Path, extra_policy: Option<&Path>) -> Result<PathBuf> {
+    let service_dir = dir.join("services");
+    // start from scratch, in case the bridge has been moved or uninstalled.
+    let _ = fs::remove_dir_all(&service_dir);
+    fs::create_dir_all(&service_dir)
+        .with_context(|| format!("failed to create {}", service_dir.display()))?;
+
+    // without the bridge, the bus still works, but nothing can be activated.
+    if let Some(bridge) = find_bridge() {
+        for name in BRIDGE_NAMES {
+            fs::write(
+                service_dir.join(format!("{}.service", name)),
+                service_file(name, &bridge),
+            )?;
+        }
+    }
+
+    let config_path = dir.join("session.conf");
+    fs::write(&config_path, config(bus_socket, &service_dir, extra_policy))
+        .with_context(|| format!("failed to write {}", config_path.display()))?;
+
+    Ok(config_path)
+}
+
+/// Looks for the bridge in `PATH`, and next to our own executable.
+fn find_bridge() -> Option<PathBuf> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+
+    env::split_paths(&path)
+        .chain(exe_dir)
+        .map(|dir| dir.join(BRIDGE))
+        .find(|path| path.is_file())
+}
+
+fn service_file(name: &str, exec: &Path) -> String {
+    format!(
+        "[D-BUS Service]\nName={}\nExec={}\n",
+        name,
+        // the Exec line is split like a shell command line.
+        shell_quote(&exec.to_string_lossy())
+    )
+}
+
+fn config(bus_socket: &Path, service_dir: &Path, extra_policy: Option<&Path>) -> String {
+    let mut config = String::from(
+        r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<!-- Generated by wsl-session-mgr, changes will be overwritten. -->
+<busconfig>
+  <type>session</type>
+  <keep_umask/>
+"#,
+    );
+
+    let _ = writeln!(
+        config,
+        "  <listen>{}</listen>",
+        xml_escape(&bus::unix_address(bus_socket))
+    );
+    config.push_str(
+        r#"  <auth>EXTERNAL</auth>
+  <standard_session_servicedirs />
+"#,
+    );
+    let _ = writeln!(
+        config,
+        "  <servicedir>{}</servicedir>",
+        xml_escape(&service_dir.to_string_lossy())
+    );
+
+    config.push_str(
+        r#"  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+  <includedir>/usr/share/dbus-1/session.d</includedir>
+  <includedir>/etc/dbus-1/session.d</includedir>
+  <include ignore_missing="yes">/etc/dbus-1/session-local.conf</include>
+  <limit name="max_incoming_bytes">1000000000</limit>
+  <limit name="max_incoming_unix_fds">250000000</limit>
+  <limit name="max_outgoing_bytes">1000000000</limit>
+  <limit name="max_outgoing_unix_fds">250000000</limit>
+  <limit name="max_message_size">1000000000</limit>
+  <limit name="service_start_timeout">120000</limit>
+  <limit name="auth_timeout">240000</limit>
+  <limit name="pending_fd_timeout">150000</limit>
+  <limit name="max_completed_connections">100000</limit>
+  <limit name="max_incomplete_connections">10000</limit>
+  <limit name="max_connections_per_user">100000</limit>
+  <limit name="max_pending_service_starts">10000</limit>
+  <limit name="max_names_per_connection">50000</limit>
+  <limit name="max_match_rules_per_connection">50000</limit>
+  <limit name="max_replies_per_connection">50000</limit>
+"#,
+    );
+
+    if let Some(extra_policy) = extra_policy {
+        let _ = writeln!(
+            config,
+            "  <include ignore_missing=\"yes\">{}</include>",
+            xml_escape(&extra_policy.to_string_lossy())
+        );
+    }
+    config.push_str("</busconfig>\n");
+
+    config
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn shell_quote(s: &str) -> String {
+    if s.bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-_/.".contains(&b))
+    {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = config(
+            Path::new("/run/user/1000/bus"),
+            Path::new("/run/user/1000/wsl-session-manager/services"),
+            Some(Path::new(
+                "/home/user/.config/wsl-session-manager/dbus.conf",
+            )),
+        );
+
+        assert!(config.contains("<listen>unix:path=/run/user/1000/bus</listen>"));
+        assert!(
+            config.contains("<servicedir>/run/user/1000/wsl-session-manager/services</servicedir>")
+        );
+        assert!(config.contains(
+            "<include ignore_missing=\"yes\">/home/user/.config/wsl-session-manager/dbus.conf</include>\n</busconfig>"
+        ));
+    }
+
+    #[test]
+    fn test_escaping() {
+        let config = config(
+            Path::new("/tmp/a b&c/bus"),
+            Path::new("/tmp/<services>"),
+            Some(Path::new("/home/\"user\"/dbus.conf")),
+        );
+
+        assert!(config.contains("<listen>unix:path=/tmp/a%20b%26c/bus</listen>"));
+        assert!(config.contains("<servicedir>/tmp/&lt;services&gt;</servicedir>"));
+        assert!(config.contains(">/home/&quot;user&quot;/dbus.conf</include>"));
+    }
+
+    #[test]
+    fn test_service_file() {
+        assert_eq!(
+            service_file(
+                "com.github.raytar.WSL",
+                Path::new("/usr/bin/xdp-wsl-bridge")
+            ),
+            "[D-BUS Service]\nName=com.github.raytar.WSL\nExec=/usr/bin/xdp-wsl-bridge\n"
+        );
+        assert_eq!(
+            service_file(
+                "com.github.raytar.WSL",
+                Path::new("/home/o'neil/bin/bridge")
+            ),
+            "[D-BUS Service]\nName=com.github.raytar.WSL\nExec='/home/o'\\''neil/bin/bridge'\n"
+        );
+    }
+}
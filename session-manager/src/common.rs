@@ -3,4 +3,183 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-pub static SOCKET_PATH: &str = "/tmp/wsl-session-manager.sock";
+use anyhow::{bail, Context, Result};
+use nix::unistd::Uid;
+use std::env;
+use std::fs::{self, DirBuilder, File};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+static SOCKET_NAME: &str = "wsl-session-manager.sock";
+
+/// Created in runtime directories that were created by us, so that we know to remove them when
+/// the session is stopped.
+static CREATED_MARKER: &str = ".wsl-session-manager";
+
+/// Returns the path of the daemon's socket in the runtime directory.
+pub fn socket_path(runtime_dir: &Path) -> PathBuf {
+    runtime_dir.join(SOCKET_NAME)
+}
+
+/// Returns the user's runtime directory, creating it if necessary.
+///
+/// `$XDG_RUNTIME_DIR` is used if it is set, then `/run/user/<uid>` like on systemd distros, and
+/// lastly a per-user directory in `/tmp`. A directory is only used if it is owned by the current
+/// user and has mode 0700.
+pub fn runtime_dir() -> Result<PathBuf> {
+    find_private_dir(&runtime_dir_candidates())
+}
+
+/// Returns the user's runtime directory if it exists.
+pub fn existing_runtime_dir() -> Option<PathBuf> {
+    existing_private_dir(&runtime_dir_candidates())
+}
+
+fn runtime_dir_candidates() -> Vec<PathBuf> {
+    let uid = Uid::current();
+    let mut candidates = Vec::new();
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        candidates.push(PathBuf::from(dir));
+    }
+    candidates.push(Path::new("/run/user").join(uid.to_string()));
+    candidates.push(env::temp_dir().join(format!("runtime-{}", uid)));
+    candidates
+}
+
+fn existing_private_dir(candidates: &[PathBuf]) -> Option<PathBuf> {
+    // the dir could have been created by someone else, so it is not enough that it exists.
+    candidates.iter().find(|dir| is_private_dir(dir)).cloned()
+}
+
+/// Returns the first candidate that is a private directory, or else the first one that could be
+/// created as one.
+fn find_private_dir(candidates: &[PathBuf]) -> Result<PathBuf> {
+    if let Some(dir) = existing_private_dir(candidates) {
+        return Ok(dir);
+    }
+
+    for dir in candidates {
+        // only the directory itself is created, its parent (like /run/user) must already exist.
+        if DirBuilder::new().mode(0o700).create(dir).is_ok() {
+            File::create(dir.join(CREATED_MARKER))
+                .with_context(|| format!("failed to initialize {}", dir.display()))?;
+            return Ok(dir.clone());
+        }
+    }
+
+    bail!(
+        "no usable runtime directory, one of {} must be a directory owned by the current user with mode 0700",
+        candidates
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+/// Removes the runtime directory, but only if it was created by us.
+pub fn remove_runtime_dir(dir: &Path) -> Result<()> {
+    if dir.join(CREATED_MARKER).exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("failed to remove {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Checks that the path is a directory that only the current user has access to.
+fn is_private_dir(path: &Path) -> bool {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => {
+            metadata.is_dir()
+                && metadata.uid() == Uid::current().as_raw()
+                && metadata.mode() & 0o077 == 0
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns the directory containing the session manager's configuration.
+pub fn config_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Returns the directory where the session manager keeps logs.
+pub fn state_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+fn xdg_dir(var: &str, default: &str) -> Result<PathBuf> {
+    // relative paths are invalid according to the XDG base directory specification, and should be ignored.
+    let base = match env::var_os(var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => {
+            let home = env::var_os("HOME").context("HOME is not set")?;
+            Path::new(&home).join(default)
+        }
+    };
+    Ok(base.join("wsl-session-manager"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn make_dir(path: &Path, mode: u32) {
+        fs::create_dir(path).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn test_reuse_private_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let existing = tmp.path().join("existing");
+        make_dir(&existing, 0o700);
+
+        // existing directories are preferred over creating one.
+        let candidates = [tmp.path().join("other"), existing.clone()];
+        assert_eq!(existing_private_dir(&candidates), Some(existing.clone()));
+        let dir = find_private_dir(&candidates).unwrap();
+        assert_eq!(dir, existing);
+        assert!(!candidates[0].exists());
+
+        // directories that we didn't create are left alone.
+        remove_runtime_dir(&dir).unwrap();
+        assert!(dir.exists());
+    }
+
+    #[test]
+    fn test_create_fallback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let public = tmp.path().join("public");
+        make_dir(&public, 0o755);
+        let fallback = tmp.path().join("fallback");
+
+        let candidates = [
+            public.clone(),
+            tmp.path().join("missing-parent/runtime"),
+            fallback.clone(),
+        ];
+        assert_eq!(existing_private_dir(&candidates), None);
+        let dir = find_private_dir(&candidates).unwrap();
+        assert_eq!(dir, fallback);
+        assert!(is_private_dir(&dir));
+
+        // it is reused the next time.
+        fs::write(dir.join("socket"), "").unwrap();
+        assert_eq!(find_private_dir(&candidates).unwrap(), fallback);
+        assert!(dir.join("socket").exists());
+
+        remove_runtime_dir(&dir).unwrap();
+        assert!(!dir.exists());
+        assert!(public.exists());
+    }
+
+    #[test]
+    fn test_no_usable_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let public = tmp.path().join("public");
+        make_dir(&public, 0o755);
+
+        assert!(find_private_dir(&[public]).is_err());
+    }
+}
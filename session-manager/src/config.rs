@@ -0,0 +1,49 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The settings in `~/.config/wsl-session-manager/config.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// How many seconds to wait after the last session has ended before shutting down.
+    /// The session manager keeps running forever if this is not set.
+    pub idle_timeout: Option<u64>,
+}
+
+impl Config {
+    /// Reads the config file, or returns the default config if there is none.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(config) => toml::from_str(&config).with_context(|| format!("{}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("config.toml");
+
+        assert_eq!(Config::load(&path).unwrap().idle_timeout, None);
+
+        fs::write(&path, "idle-timeout = 300").unwrap();
+        assert_eq!(Config::load(&path).unwrap().idle_timeout, Some(300));
+
+        fs::write(&path, "idle-timeout = \"soon\"").unwrap();
+        assert!(Config::load(&path).is_err());
+    }
+}
@@ -4,52 +4,83 @@
 // https://opensource.org/licenses/MIT
 
 use anyhow::{anyhow, bail, Context, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
 use nix::sys::wait::waitpid;
-use nix::unistd::{fork, setsid, ForkResult};
+use nix::unistd::{fork, getsid, setsid, ForkResult, Pid, Uid};
 use single_instance::SingleInstance;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
-use std::io::{BufRead, Write};
-use std::os::unix::net::UnixListener;
-use std::process::{exit, Command, Stdio};
+use std::io::{self, BufRead, BufReader};
+use std::iter;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{self, exit, Child, Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::common::SOCKET_PATH;
+use crate::bus;
+use crate::busconfig;
+use crate::common::{config_dir, remove_runtime_dir, runtime_dir, socket_path, state_dir};
+use crate::config::Config;
+use crate::leases::Leases;
+use crate::protocol::{self, ExternalBus, Reply, Request, Response, Status};
+use crate::services;
+use crate::supervisor::{unix_time, Restart, StopWithParent, Supervisor};
 
-fn dbus_launch() -> Result<(String, u32)> {
-    let out = Command::new("dbus-daemon")
-        .arg("--fork")
-        .arg("--syslog-only")
-        .arg("--print-pid")
-        .arg("--print-address")
-        .arg("--session")
-        .output()
-        .context("failed to run dbus-daemon")?;
-
-    if !out.status.success() {
-        bail!(
-            "dbus-daemon exited with code: {}\n{}",
-            out.status.code().unwrap(),
-            String::from_utf8_lossy(&out.stderr)
-        )
+/// How often to check whether the sessions have ended, when there is an idle timeout.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts a dbus-daemon listening on `socket`, and waits for it to be ready.
+///
+/// `config_file` must configure the bus to listen on `socket`.
+fn dbus_launch(socket: &Path, config_file: &Path) -> Result<Child> {
+    // dbus-daemon refuses to start if the socket already exists, so remove it unless someone is using it.
+    if UnixStream::connect(socket).is_ok() {
+        bail!("a bus is already listening on {}", socket.display());
     }
+    let _ = fs::remove_file(socket);
 
-    let mut lines_iter = out.stdout.lines();
+    let mut child = Command::new("dbus-daemon")
+        .arg("--nofork")
+        .arg("--syslog-only")
+        .arg("--print-address")
+        .arg(format!("--config-file={}", config_file.display()))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stop_with_parent()
+        .spawn()
+        .map_err(|e| {
+            // dbus-broker can only be launched by systemd, in which case the bus would have been reused.
+            if e.kind() == io::ErrorKind::NotFound && is_in_path("dbus-broker-launch") {
+                anyhow!(
+                    "dbus-daemon is not installed, and dbus-broker is only supported with systemd"
+                )
+            } else {
+                anyhow::Error::new(e).context("failed to run dbus-daemon")
+            }
+        })?;
 
-    // the lines iterator returns a Result<_>, so we need to use the '?' operator twice.
-    let addr = lines_iter
-        .next()
-        .ok_or_else(|| anyhow!("dbus-daemon did not print address to stdout"))?
+    // the address is printed once the bus is ready to accept connections.
+    let mut addr = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut addr)
         .context("failed to read output from dbus-daemon")?;
 
-    // is this silly?
-    let pid = lines_iter
-        .next()
-        .ok_or_else(|| anyhow!("dbus-daemon did not print pid to stdout"))?
-        .context("failed to read output from dbus-daemon")?
-        .parse::<u32>()
-        .context("failed to parse pid")?;
+    if addr.trim().is_empty() {
+        let status = child.wait()?;
+        bail!("dbus-daemon exited with {}", status);
+    }
+
+    Ok(child)
+}
 
-    Ok((addr, pid))
+fn is_in_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
 }
 
 pub fn start_daemon() -> Result<()> {
@@ -75,34 +106,253 @@ pub fn start_daemon() -> Result<()> {
 
 pub fn run_daemon() -> Result<()> {
     let machine_id = machine_uid::get().map_err(|e| anyhow!("failed to get machine id: {}", e))?;
+    // the runtime dir is inherited by everything that we start.
+    let runtime_dir = runtime_dir()?;
+    env::set_var("XDG_RUNTIME_DIR", &runtime_dir);
+    let socket_path = socket_path(&runtime_dir);
 
     // we use the machine id in the instance name, because otherwise it is not possible to run
     // wsl-session-manager in multiple distros at the same time.
-    let si = SingleInstance::new(format!("wsl-session-manager:{}", machine_id).as_str())?;
+    // the uid is used such that each user gets their own daemon, and the socket path such that
+    // a user with a different runtime dir does too.
+    let si = SingleInstance::new(
+        format!(
+            "wsl-session-manager:{}:{}:{}",
+            machine_id,
+            Uid::current(),
+            socket_path.display()
+        )
+        .as_str(),
+    )?;
     if !si.is_single() {
         exit(0);
     }
 
-    // launch the dbus-daemon and get the output
-    let (addr, pid) = dbus_launch()?;
+    // reuse the user's bus if there already is one, like when the distro runs systemd.
+    // the systemd user bus is in the same place as ours, so it is found the same way.
+    let bus_socket = runtime_dir.join("bus");
+    let own_address = bus::unix_address(&bus_socket);
+    let existing_address = env::var("DBUS_SESSION_BUS_ADDRESS")
+        .ok()
+        .into_iter()
+        .chain(iter::once(own_address.clone()))
+        .find(|address| bus::is_reachable(address));
+
+    let (bus_address, bus) = match existing_address {
+        Some(address) => (address, None),
+        // launch the dbus-daemon. the bus address stays the same when it is restarted.
+        None => (
+            own_address,
+            Some(Supervisor::start("dbus-daemon", Restart::Always, {
+                let generated_dir = runtime_dir.join("wsl-session-manager");
+                let extra_policy = config_dir().ok().map(|dir| dir.join("dbus.conf"));
+                // the config is written every time, so that it picks up changes like a newly installed bridge.
+                move || {
+                    let config_file =
+                        busconfig::write(&generated_dir, &bus_socket, extra_policy.as_deref())?;
+                    dbus_launch(&bus_socket, &config_file)
+                }
+            })?),
+        ),
+    };
+
+    let mut session = Session {
+        started: unix_time(SystemTime::now()),
+        runtime_dir,
+        bus_address,
+        bus,
+        services: Vec::new(),
+        leases: Leases::new(Instant::now()),
+        idle_timeout: None,
+        errors: Vec::new(),
+    };
+
+    match config_dir().and_then(|dir| Config::load(&dir.join("config.toml"))) {
+        Ok(config) => session.idle_timeout = config.idle_timeout.map(Duration::from_secs),
+        Err(e) => session.errors.push(format!("{:#}", e)),
+    }
+
+    session.start_services();
 
     // set up the socket
-    let _ = fs::remove_file(SOCKET_PATH);
-    let listener = UnixListener::bind(SOCKET_PATH)?;
+    let _ = fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path)?;
 
     // handle connections
     loop {
-        let (mut socket, _) = listener.accept()?;
-        // don't care about failed writes
-        let _ = socket.write_all(
-            format!(
-                "DBUS_SESSION_BUS_ADDRESS='{}';
-export DBUS_SESSION_BUS_ADDRESS;
-DBUS_SESSION_BUS_PID='{}';
-export DBUS_SESSION_BUS_PID;",
-                &addr, &pid
-            )
-            .as_ref(),
+        // wake up regularly to check whether we have become idle.
+        let timeout = match session.idle_timeout {
+            Some(_) => IDLE_CHECK_INTERVAL.as_millis() as i32,
+            None => -1,
+        };
+
+        let mut fds = [PollFd::new(listener.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(0) | Err(Errno::EINTR) => {}
+            Ok(_) => {
+                let (socket, _) = listener.accept()?;
+                handle_connection(&mut session, socket, &socket_path)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        if session.is_idle(Instant::now()) {
+            session.stop();
+            let _ = fs::remove_file(&socket_path);
+            exit(0);
+        }
+    }
+}
+
+fn handle_connection(session: &mut Session, socket: UnixStream, socket_path: &Path) -> Result<()> {
+    let peer = match getsockopt(socket.as_raw_fd(), PeerCredentials) {
+        Ok(peer) if peer.uid() == Uid::current().as_raw() => peer,
+        // only the user that the daemon belongs to is allowed to use it.
+        _ => return Ok(()),
+    };
+
+    // don't let a client that never sends anything block everyone else.
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut reader = BufReader::new(&socket);
+
+    let reply = match protocol::receive::<Request>(&mut reader) {
+        Ok(request) if request.command == protocol::Command::Stop => {
+            session.stop();
+            let _ = fs::remove_file(socket_path);
+            let _ = protocol::send(&mut &socket, &Response::new(Reply::Stopped));
+            // the client knows that we are gone once the connection is closed.
+            exit(0);
+        }
+        Ok(request) => session.handle(request.command, peer.pid()),
+        Err(e) => Reply::Error(format!("invalid request: {:#}", e)),
+    };
+
+    // don't care about failed writes
+    let _ = protocol::send(&mut &socket, &Response::new(reply));
+    Ok(())
+}
+
+/// The processes managed by the daemon.
+struct Session {
+    /// When the daemon was started, in seconds since the epoch.
+    started: u64,
+    runtime_dir: PathBuf,
+    bus_address: String,
+    /// `None` if we are using a bus that someone else started.
+    bus: Option<Supervisor>,
+    services: Vec<Supervisor>,
+    /// The sessions that have sourced our environment.
+    leases: Leases,
+    /// How long to keep running after the last session has ended, or `None` to run forever.
+    idle_timeout: Option<Duration>,
+    /// Problems with the configuration, which are reported in the status.
+    errors: Vec<String>,
+}
+
+impl Session {
+    /// Starts the services from the config directory, now that the bus is running.
+    fn start_services(&mut self) {
+        let dirs = config_dir().and_then(|config_dir| Ok((config_dir, state_dir()?)));
+        let (config_dir, state_dir) = match dirs {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                self.errors.push(format!("{:#}", e));
+                return;
+            }
+        };
+
+        let log_dir = state_dir.join("logs");
+        let (services, errors) = services::load(&config_dir.join("services"));
+        self.errors.extend(errors);
+
+        for service in services {
+            let name = service.name.clone();
+            let bus_address = self.bus_address.clone();
+            let log_dir = log_dir.clone();
+
+            match Supervisor::start(&name, service.restart, move || {
+                service.spawn(&bus_address, &log_dir)
+            }) {
+                Ok(supervisor) => self.services.push(supervisor),
+                Err(e) => self.errors.push(format!("{}: {:#}", name, e)),
+            }
+        }
+    }
+
+    /// Handles a request from the process `peer`.
+    fn handle(&mut self, command: protocol::Command, peer: i32) -> Reply {
+        match command {
+            protocol::Command::Env => {
+                // the client is usually run by a shell that is about to source the environment,
+                // so the session lasts for as long as the terminal that the client runs in.
+                if let Ok(sid) = getsid(Some(Pid::from_raw(peer))) {
+                    self.leases.add(sid.as_raw() as u32);
+                }
+                Reply::Env(self.env())
+            }
+            protocol::Command::Status => Reply::Status(Status {
+                pid: process::id(),
+                started: self.started,
+                bus_address: self.bus_address.clone(),
+                external_bus: match self.bus {
+                    Some(_) => None,
+                    None => Some(ExternalBus {
+                        reachable: bus::is_reachable(&self.bus_address),
+                        owner: bus::owner(&self.bus_address),
+                    }),
+                },
+                children: self
+                    .bus
+                    .iter()
+                    .chain(&self.services)
+                    .map(Supervisor::status)
+                    .collect(),
+                sessions: self.leases.pids(),
+                idle_timeout: self.idle_timeout.map(|timeout| timeout.as_secs()),
+                errors: self.errors.clone(),
+            }),
+            protocol::Command::Stop => unreachable!("stop is handled by the caller"),
+        }
+    }
+
+    /// The environment variables that clients should set.
+    fn env(&self) -> BTreeMap<String, String> {
+        let mut env = BTreeMap::new();
+        env.insert(
+            String::from("XDG_RUNTIME_DIR"),
+            self.runtime_dir.display().to_string(),
         );
+        env.insert(
+            String::from("DBUS_SESSION_BUS_ADDRESS"),
+            self.bus_address.clone(),
+        );
+        // the pid is only exported for a bus that we started, since it is not ours to kill otherwise.
+        if let Some(pid) = self.bus.as_ref().and_then(|bus| bus.status().pid) {
+            env.insert(String::from("DBUS_SESSION_BUS_PID"), pid.to_string());
+        }
+        env
+    }
+
+    /// Checks whether the idle timeout has passed since the last session ended.
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.leases.prune(now);
+        match (self.idle_timeout, self.leases.idle_for(now)) {
+            (Some(timeout), Some(idle)) => idle >= timeout,
+            _ => false,
+        }
+    }
+
+    fn stop(&mut self) {
+        // stop the services first, so that they don't see the bus go away.
+        for service in self.services.iter_mut().rev() {
+            service.stop();
+        }
+        if let Some(bus) = &mut self.bus {
+            bus.stop();
+            let _ = fs::remove_dir_all(self.runtime_dir.join("wsl-session-manager"));
+        }
+
+        // the runtime dir belongs to this session if we created it.
+        let _ = remove_runtime_dir(&self.runtime_dir);
     }
 }
@@ -0,0 +1,150 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! Keeps track of the login sessions that are using the session manager.
+//!
+//! A session is identified by its session leader, which is usually the shell of a terminal, or
+//! the process that started it. A lease ends when the session leader exits.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq)]
+struct Lease {
+    pid: u32,
+    /// Used to tell the session leader apart from a later process that reuses its pid.
+    start_time: u64,
+}
+
+pub struct Leases {
+    leases: Vec<Lease>,
+    /// When the last lease ended, or when we started if there never was one.
+    idle_since: Option<Instant>,
+}
+
+impl Leases {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            leases: Vec::new(),
+            idle_since: Some(now),
+        }
+    }
+
+    /// Adds a lease for the session led by `pid`.
+    pub fn add(&mut self, pid: u32) {
+        let lease = match start_time(pid) {
+            Some(start_time) => Lease { pid, start_time },
+            // the process is already gone.
+            None => return,
+        };
+
+        if !self.leases.contains(&lease) {
+            self.leases.push(lease);
+        }
+        self.idle_since = None;
+    }
+
+    /// Removes the leases of sessions that have ended.
+    pub fn prune(&mut self, now: Instant) {
+        self.leases
+            .retain(|lease| start_time(lease.pid) == Some(lease.start_time));
+
+        if self.leases.is_empty() && self.idle_since.is_none() {
+            self.idle_since = Some(now);
+        }
+    }
+
+    /// Returns how long there have been no sessions.
+    pub fn idle_for(&self, now: Instant) -> Option<Duration> {
+        self.idle_since
+            .map(|idle_since| now.saturating_duration_since(idle_since))
+    }
+
+    /// The pids of the session leaders.
+    pub fn pids(&self) -> Vec<u32> {
+        self.leases.iter().map(|lease| lease.pid).collect()
+    }
+}
+
+/// Returns when the process was started, in clock ticks since boot.
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // the command name is in parentheses and can contain spaces, so skip past it.
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+
+    // zombies have exited, but have not been waited for yet.
+    if fields.next()? == "Z" {
+        return None;
+    }
+    // the start time is the 22nd field, and we are at the 4th.
+    fields.nth(18)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{self, Command};
+
+    #[test]
+    fn test_start_time() {
+        assert!(start_time(process::id()).is_some());
+        assert_eq!(start_time(process::id()), start_time(process::id()));
+        assert_eq!(start_time(u32::MAX), None);
+    }
+
+    #[test]
+    fn test_leases() {
+        let start = Instant::now();
+        let mut leases = Leases::new(start);
+        assert_eq!(leases.idle_for(start), Some(Duration::ZERO));
+
+        leases.add(process::id());
+        leases.add(process::id());
+        assert_eq!(leases.pids(), [process::id()]);
+        assert_eq!(leases.idle_for(start), None);
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        leases.add(child.id());
+        assert_eq!(leases.pids(), [process::id(), child.id()]);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        leases.prune(start);
+        assert_eq!(leases.pids(), [process::id()]);
+        assert_eq!(leases.idle_for(start), None);
+
+        // a process that has exited can't get a lease.
+        leases.add(child.id());
+        assert_eq!(leases.pids(), [process::id()]);
+    }
+
+    #[test]
+    fn test_idle() {
+        let start = Instant::now();
+        let mut leases = Leases::new(start);
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        leases.add(child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let later = start + Duration::from_secs(10);
+        leases.prune(later);
+        assert!(leases.pids().is_empty());
+        assert_eq!(leases.idle_for(later), Some(Duration::ZERO));
+        assert_eq!(
+            leases.idle_for(later + Duration::from_secs(5)),
+            Some(Duration::from_secs(5))
+        );
+
+        // pruning again doesn't reset the idle time.
+        leases.prune(later + Duration::from_secs(5));
+        assert_eq!(
+            leases.idle_for(later + Duration::from_secs(5)),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
@@ -3,30 +3,211 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use anyhow::{bail, Result};
-use common::SOCKET_PATH;
+use anyhow::{bail, Context, Result};
+use common::{existing_runtime_dir, socket_path};
 use daemon::{run_daemon, start_daemon};
+use protocol::{Command, Reply, Request, Response};
+use shell::Shell;
 use std::env;
-use std::io::{self, prelude::*, ErrorKind};
+use std::io::{self, prelude::*, BufReader, ErrorKind};
 use std::os::unix::net::UnixStream;
 use std::process::exit;
 use std::thread::sleep;
 use std::time::{Duration, SystemTime};
 
+mod bus;
+mod busconfig;
 mod common;
+mod config;
 mod daemon;
+mod leases;
+mod protocol;
+mod services;
+mod shell;
+mod supervisor;
 
-#[allow(clippy::iter_nth_zero)]
 fn main() -> Result<()> {
-    // check if "daemon" has been passed as an argument
-    if let Some(arg) = env::args().nth(1) {
-        if arg != "daemon" {
-            eprintln!("usage: {} [daemon]", env::args().nth(0).unwrap());
-            exit(1);
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        // no arguments is the same as "env", for compatibility with existing ~/.profile snippets.
+        None => print_env(Shell::Sh),
+        Some("env") => print_env(parse_env_args(&args[2..])?),
+        Some("status") => match try_connect()? {
+            Some(stream) => print_status(&stream),
+            None => {
+                println!("wsl-session-mgr: not running");
+                // same as `systemctl status` for inactive units.
+                exit(3);
+            }
+        },
+        Some("stop") => stop(),
+        Some("restart") => {
+            stop()?;
+            print_status(&connect()?)
         }
-        run_daemon()?
+        Some("daemon") => run_daemon(),
+        Some(_) => usage(&args[0]),
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "usage: {} [env [--shell sh|fish|csh|nu|json] | status | stop | restart | daemon]",
+        program
+    );
+    exit(1);
+}
+
+fn parse_env_args(args: &[String]) -> Result<Shell> {
+    match args {
+        [] => Ok(Shell::Sh),
+        [option, shell] if option == "--shell" => shell.parse(),
+        [option] if option.starts_with("--shell=") => option["--shell=".len()..].parse(),
+        _ => bail!("unexpected arguments: {}", args.join(" ")),
+    }
+}
+
+fn print_env(shell: Shell) -> Result<()> {
+    let stream = connect()?;
+    match request(&stream, Command::Env)? {
+        Reply::Env(env) => io::stdout().write_all(shell.format(&env).as_bytes())?,
+        reply => bail!("unexpected reply from daemon: {:?}", reply),
+    }
+    Ok(())
+}
+
+fn print_status(stream: &UnixStream) -> Result<()> {
+    let status = match request(stream, Command::Status)? {
+        Reply::Status(status) => status,
+        reply => bail!("unexpected reply from daemon: {:?}", reply),
+    };
+    let now = supervisor::unix_time(SystemTime::now());
+
+    println!(
+        "wsl-session-mgr: running (pid {}, up {})",
+        status.pid,
+        format_duration(now.saturating_sub(status.started))
+    );
+    println!("bus address: {}", status.bus_address);
+    if let Some(bus) = &status.external_bus {
+        match (&bus.owner, bus.reachable) {
+            (Some(owner), _) => println!(
+                "using existing bus (owned by {}, pid {})",
+                owner.program.as_deref().unwrap_or("unknown"),
+                owner.pid
+            ),
+            (None, true) => println!("using existing bus"),
+            (None, false) => println!("using existing bus (not reachable)"),
+        }
+    }
+
+    println!(
+        "sessions: {}",
+        match status.sessions.len() {
+            0 => String::from("none"),
+            _ => status
+                .sessions
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    );
+    match status.idle_timeout {
+        Some(timeout) => println!("idle timeout: {}", format_duration(timeout)),
+        None => println!("idle timeout: never"),
+    }
+
+    for child in &status.children {
+        match (child.pid, child.started) {
+            (Some(pid), Some(started)) => println!(
+                "{}: running (pid {}, up {})",
+                child.name,
+                pid,
+                format_duration(now.saturating_sub(started))
+            ),
+            _ => println!("{}: not running", child.name),
+        }
+
+        for exit in &child.exits {
+            println!(
+                "  {} ago: {} after {}",
+                format_duration(now.saturating_sub(exit.time)),
+                exit.status,
+                format_duration(exit.uptime)
+            );
+        }
+    }
+
+    for error in &status.errors {
+        println!("error: {}", error);
+    }
+
+    Ok(())
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Stops the daemon if it is running, and waits for it to exit.
+fn stop() -> Result<()> {
+    let stream = match try_connect()? {
+        Some(stream) => stream,
+        None => return Ok(()),
+    };
+
+    match request(&stream, Command::Stop)? {
+        Reply::Stopped => {}
+        reply => bail!("unexpected reply from daemon: {:?}", reply),
     }
 
+    // the daemon closes the connection when it exits.
+    let _ = (&stream).read_to_end(&mut Vec::new());
+    Ok(())
+}
+
+/// Sends a request to the daemon and returns its reply.
+fn request(mut stream: &UnixStream, command: Command) -> Result<Reply> {
+    // stopping has to wait for the supervised processes to exit, so give the daemon some time.
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    protocol::send(&mut stream, &Request::new(command))?;
+    let response: Response = protocol::receive(&mut BufReader::new(stream))
+        .context("failed to read response from daemon")?;
+
+    match response.reply {
+        Reply::Error(message) => bail!("daemon error: {}", message),
+        reply => Ok(reply),
+    }
+}
+
+/// Connects to the daemon, or returns `None` if it is not running.
+fn try_connect() -> Result<Option<UnixStream>> {
+    // the daemon creates the runtime dir, so it can't be running if there isn't one.
+    let socket_path = match existing_runtime_dir() {
+        Some(dir) => socket_path(&dir),
+        None => return Ok(None),
+    };
+
+    match UnixStream::connect(socket_path) {
+        Ok(stream) => Ok(Some(stream)),
+        Err(e) if is_not_running(&e) => Ok(None),
+        Err(e) => bail!("an unknown error occurred: {}", e),
+    }
+}
+
+fn is_not_running(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::ConnectionRefused | ErrorKind::NotFound)
+}
+
+/// Connects to the daemon, starting it if it is not already running.
+fn connect() -> Result<UnixStream> {
     let start_time = SystemTime::now();
     loop {
         // make sure that we don't hang for longer than a second before giving up
@@ -34,17 +215,9 @@ fn main() -> Result<()> {
             bail!("could not connect to daemon");
         }
 
-        match UnixStream::connect(SOCKET_PATH) {
-            Ok(mut stream) => {
-                run_client(&mut stream)?;
-                break;
-            }
-            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {}
-            Err(e) if e.kind() == ErrorKind::NotFound => {}
-            Err(e) => {
-                bail!("an unknown error occurred: {}", e);
-            }
-        };
+        if let Some(stream) = try_connect()? {
+            return Ok(stream);
+        }
 
         // attempt to start the daemon
         start_daemon()?;
@@ -52,17 +225,4 @@ fn main() -> Result<()> {
         // wait for daemon to start
         sleep(Duration::new(0, 10_000_000));
     }
-
-    Ok(())
-}
-
-fn run_client(socket: &mut UnixStream) -> Result<()> {
-    socket
-        .set_read_timeout(Some(Duration::new(0, 100_000_000)))
-        .expect("Failed to set timeout");
-
-    let mut buffer = String::new();
-    socket.read_to_string(&mut buffer)?;
-    io::stdout().write_all(buffer.as_bytes())?;
-    Ok(())
 }
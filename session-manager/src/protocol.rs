@@ -0,0 +1,161 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! The protocol spoken between the client and the daemon over the daemon's socket.
+//!
+//! The client sends a single request as a line of JSON, and the daemon answers with a single line
+//! of JSON. Both messages carry the protocol version, so that a client and a daemon from different
+//! versions of wsl-session-mgr can tell that they don't understand each other.
+
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use crate::bus::Owner;
+use crate::supervisor::ChildStatus;
+
+/// Must be incremented whenever a change is made that older clients or daemons can't handle.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Command {
+    /// Get the environment variables that clients should set.
+    Env,
+    Status,
+    /// Stop the supervised processes, and then the daemon itself.
+    Stop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    pub reply: Reply,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reply {
+    Env(BTreeMap<String, String>),
+    Status(Status),
+    /// Everything has been stopped, and the daemon exits after sending this.
+    Stopped,
+    Error(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// The pid of the daemon.
+    pub pid: u32,
+    /// When the daemon was started, in seconds since the epoch.
+    pub started: u64,
+    pub bus_address: String,
+    /// Set when using a bus that wasn't started by the daemon, like the systemd user bus.
+    #[serde(default)]
+    pub external_bus: Option<ExternalBus>,
+    pub children: Vec<ChildStatus>,
+    /// The session leaders of the sessions that use the daemon.
+    #[serde(default)]
+    pub sessions: Vec<u32>,
+    /// How many seconds the daemon keeps running after the last session has ended.
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// Problems with the configuration.
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExternalBus {
+    pub reachable: bool,
+    pub owner: Option<Owner>,
+}
+
+/// Used to check the version before trying to parse the rest of a message.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl Request {
+    pub fn new(command: Command) -> Self {
+        Self {
+            version: VERSION,
+            command,
+        }
+    }
+}
+
+impl Response {
+    pub fn new(reply: Reply) -> Self {
+        Self {
+            version: VERSION,
+            reply,
+        }
+    }
+}
+
+/// Writes a message as a single line.
+pub fn send<T: Serialize>(stream: &mut impl Write, message: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    Ok(())
+}
+
+/// Reads a single message, and checks that it uses the same protocol version as us.
+pub fn receive<T: DeserializeOwned>(stream: &mut impl BufRead) -> Result<T> {
+    let mut line = String::new();
+    if stream.read_line(&mut line)? == 0 {
+        bail!("connection closed unexpectedly");
+    }
+
+    let header: Header = serde_json::from_str(&line).context("malformed message")?;
+    if header.version != VERSION {
+        bail!(
+            "protocol version mismatch (got {}, expected {})",
+            header.version,
+            VERSION
+        );
+    }
+
+    serde_json::from_str(&line).context("malformed message")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = Vec::new();
+        send(&mut buffer, &Request::new(Command::Stop)).unwrap();
+        assert_eq!(buffer, b"{\"version\":1,\"command\":\"stop\"}\n");
+
+        let request: Request = receive(&mut Cursor::new(buffer)).unwrap();
+        assert_eq!(request.command, Command::Stop);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut stream = Cursor::new(b"{\"version\":2,\"command\":\"something-new\"}\n");
+        let err = receive::<Request>(&mut stream).unwrap_err();
+        assert!(err.to_string().contains("version mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn closed() {
+        assert!(receive::<Request>(&mut Cursor::new(b"")).is_err());
+    }
+}
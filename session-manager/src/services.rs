@@ -0,0 +1,179 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+//! User services that are started by the daemon, for distros that don't run systemd.
+//!
+//! Each service is described by a file in `~/.config/wsl-session-manager/services`, named after
+//! the service:
+//!
+//! ```toml
+//! # ~/.config/wsl-session-manager/services/xdp-wsl-bridge.toml
+//! command = ["xdp-wsl-bridge"]
+//! environment = { RUST_LOG = "debug" }
+//! restart = "on-failure" # or "always" or "never"
+//! depends-on-bus = true
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+use crate::bus;
+use crate::supervisor::{Restart, StopWithParent};
+
+/// Logs larger than this are rotated when the service is started.
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Service {
+    #[serde(skip)]
+    pub name: String,
+    /// The program and its arguments.
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+    #[serde(default)]
+    pub restart: Restart,
+    /// Whether the service has to wait for the bus before it can be started.
+    #[serde(default = "default_depends_on_bus")]
+    pub depends_on_bus: bool,
+}
+
+fn default_depends_on_bus() -> bool {
+    true
+}
+
+impl Service {
+    fn parse(name: &str, config: &str) -> Result<Self> {
+        let mut service: Service = toml::from_str(config)?;
+        if service.command.is_empty() {
+            bail!("command must not be empty");
+        }
+        service.name = name.to_string();
+        Ok(service)
+    }
+
+    /// Starts the service with its output redirected to `<log_dir>/<name>.log`.
+    pub fn spawn(&self, bus_address: &str, log_dir: &Path) -> Result<Child> {
+        // don't start a service that needs the bus while the bus is being restarted.
+        if self.depends_on_bus && !bus::is_reachable(bus_address) {
+            bail!("the bus is not running");
+        }
+
+        let log = open_log(&log_dir.join(format!("{}.log", self.name)))?;
+
+        Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .envs(&self.environment)
+            .env("DBUS_SESSION_BUS_ADDRESS", bus_address)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .stop_with_parent()
+            .spawn()
+            .with_context(|| format!("failed to run {}", self.command[0]))
+    }
+}
+
+/// Reads all services in `dir`, sorted by name.
+///
+/// A service that can't be read doesn't prevent the others from being started, so errors are
+/// returned separately.
+pub fn load(dir: &Path) -> (Vec<Service>, Vec<String>) {
+    let mut services = Vec::new();
+    let mut errors = Vec::new();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // having no services is fine.
+        Err(_) => return (services, errors),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+
+        match fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|config| Service::parse(name, &config))
+        {
+            Ok(service) => services.push(service),
+            Err(e) => errors.push(format!("{}: {:#}", path.display(), e)),
+        }
+    }
+
+    (services, errors)
+}
+
+fn open_log(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() > MAX_LOG_SIZE) {
+        let _ = fs::rename(path, path.with_extension("log.old"));
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let service = Service::parse(
+            "bridge",
+            r#"
+                command = ["xdp-wsl-bridge", "--verbose"]
+                environment = { RUST_LOG = "debug" }
+                restart = "always"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(service.name, "bridge");
+        assert_eq!(service.command, ["xdp-wsl-bridge", "--verbose"]);
+        assert_eq!(service.environment["RUST_LOG"], "debug");
+        assert_eq!(service.restart, Restart::Always);
+        assert!(service.depends_on_bus);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let service = Service::parse("portal", r#"command = ["xdg-desktop-portal"]"#).unwrap();
+
+        assert!(service.environment.is_empty());
+        assert_eq!(service.restart, Restart::OnFailure);
+        assert!(service.depends_on_bus);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Service::parse("empty", "command = []").is_err());
+        assert!(Service::parse("missing", "restart = \"always\"").is_err());
+        assert!(Service::parse("typo", "command = [\"a\"]\nrestrat = \"never\"").is_err());
+        assert!(Service::parse("policy", "command = [\"a\"]\nrestart = \"sometimes\"").is_err());
+    }
+}
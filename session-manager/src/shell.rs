@@ -0,0 +1,161 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The shells that we know how to produce environment variable assignments for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Sh,
+    Fish,
+    Csh,
+    Nu,
+    Json,
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "sh" | "bash" | "zsh" | "dash" => Shell::Sh,
+            "fish" => Shell::Fish,
+            "csh" | "tcsh" => Shell::Csh,
+            "nu" | "nushell" => Shell::Nu,
+            "json" => Shell::Json,
+            _ => bail!("unsupported shell: {}", s),
+        })
+    }
+}
+
+impl Shell {
+    /// Formats the variables such that the output can be evaluated by the shell.
+    pub fn format(&self, vars: &BTreeMap<String, String>) -> String {
+        match self {
+            Shell::Sh => vars
+                .iter()
+                .map(|(name, value)| format!("{}={};\nexport {};\n", name, quote_sh(value), name))
+                .collect(),
+            Shell::Fish => vars
+                .iter()
+                .map(|(name, value)| format!("set -gx {} {};\n", name, quote_fish(value)))
+                .collect(),
+            Shell::Csh => vars
+                .iter()
+                .map(|(name, value)| format!("setenv {} {};\n", name, quote_csh(value)))
+                .collect(),
+            Shell::Nu => {
+                let fields: Vec<String> = vars
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", quote_nu(name), quote_nu(value)))
+                    .collect();
+                format!("load-env {{{}}}\n", fields.join(", "))
+            }
+            Shell::Json => serde_json::to_string_pretty(vars).unwrap() + "\n",
+        }
+    }
+}
+
+/// Nothing is special inside single quotes, except for the single quote itself.
+fn quote_sh(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Inside single quotes, fish only treats `\'` and `\\` as escape sequences.
+fn quote_fish(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// csh performs history substitution even inside single quotes, and does not allow newlines.
+fn quote_csh(value: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in value.chars() {
+        match c {
+            '\'' => quoted.push_str(r"'\''"),
+            '!' => quoted.push_str(r"'\!'"),
+            '\n' => quoted.push_str("'\\\n'"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Nushell double quoted strings use JSON-like escape sequences.
+fn quote_nu(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> BTreeMap<String, String> {
+        let mut vars = BTreeMap::new();
+        vars.insert(
+            String::from("DBUS_SESSION_BUS_ADDRESS"),
+            String::from("unix:path=/tmp/it's a \"bus\"!\\"),
+        );
+        vars.insert(String::from("DBUS_SESSION_BUS_PID"), String::from("42"));
+        vars
+    }
+
+    #[test]
+    fn test_sh() {
+        assert_eq!(
+            Shell::Sh.format(&vars()),
+            r#"DBUS_SESSION_BUS_ADDRESS='unix:path=/tmp/it'\''s a "bus"!\';
+export DBUS_SESSION_BUS_ADDRESS;
+DBUS_SESSION_BUS_PID='42';
+export DBUS_SESSION_BUS_PID;
+"#
+        );
+    }
+
+    #[test]
+    fn test_fish() {
+        assert_eq!(
+            Shell::Fish.format(&vars()),
+            r#"set -gx DBUS_SESSION_BUS_ADDRESS 'unix:path=/tmp/it\'s a "bus"!\\';
+set -gx DBUS_SESSION_BUS_PID '42';
+"#
+        );
+    }
+
+    #[test]
+    fn test_csh() {
+        assert_eq!(
+            Shell::Csh.format(&vars()),
+            r#"setenv DBUS_SESSION_BUS_ADDRESS 'unix:path=/tmp/it'\''s a "bus"'\!'\';
+setenv DBUS_SESSION_BUS_PID '42';
+"#
+        );
+    }
+
+    #[test]
+    fn test_nu() {
+        assert_eq!(
+            Shell::Nu.format(&vars()),
+            r#"load-env {"DBUS_SESSION_BUS_ADDRESS": "unix:path=/tmp/it's a \"bus\"!\\", "DBUS_SESSION_BUS_PID": "42"}
+"#
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let parsed: BTreeMap<String, String> =
+            serde_json::from_str(&Shell::Json.format(&vars())).unwrap();
+        assert_eq!(parsed, vars());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("bash".parse::<Shell>().unwrap(), Shell::Sh);
+        assert_eq!("nu".parse::<Shell>().unwrap(), Shell::Nu);
+        assert!("powershell".parse::<Shell>().is_err());
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use anyhow::Result;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A child that runs for less than this is considered to have crashed on startup.
+const MIN_UPTIME: Duration = Duration::from_secs(10);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a child gets to exit after SIGTERM before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of exits that are kept in the history.
+const MAX_HISTORY: usize = 10;
+
+/// When a child should be restarted after it exits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restart {
+    Always,
+    /// Restart unless the child exited successfully.
+    #[default]
+    OnFailure,
+    Never,
+}
+
+/// A snapshot of the state of a supervised child.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChildStatus {
+    pub name: String,
+    pub pid: Option<u32>,
+    /// When the child was last started, in seconds since the epoch.
+    pub started: Option<u64>,
+    /// The most recent exits, oldest first.
+    pub exits: Vec<ChildExit>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChildExit {
+    /// When the child exited, in seconds since the epoch.
+    pub time: u64,
+    /// How long the child ran for, in seconds.
+    pub uptime: u64,
+    pub status: String,
+}
+
+/// Keeps a child process running by restarting it when it exits.
+pub struct Supervisor {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Notified when the supervisor is asked to stop, and when the child exits.
+    changed: Condvar,
+}
+
+struct State {
+    status: ChildStatus,
+    stopping: bool,
+}
+
+impl Supervisor {
+    /// Starts supervising the child returned by `spawn`.
+    ///
+    /// `spawn` is called again every time the child needs to be restarted, and should not return
+    /// until the child is ready. The first start must succeed.
+    pub fn start<F>(name: &str, restart: Restart, mut spawn: F) -> Result<Self>
+    where
+        F: FnMut() -> Result<Child> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                status: ChildStatus {
+                    name: name.to_string(),
+                    pid: None,
+                    started: None,
+                    exits: Vec::new(),
+                },
+                stopping: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let child = spawn()?;
+        shared.state.lock().unwrap().status.set_running(&child);
+
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || supervise(&shared, restart, child, spawn))
+        };
+
+        Ok(Self {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn status(&self) -> ChildStatus {
+        self.shared.state.lock().unwrap().status.clone()
+    }
+
+    /// Terminates the child and waits for it to exit. It is not restarted afterwards.
+    pub fn stop(&mut self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.stopping = true;
+            self.shared.changed.notify_all();
+
+            if let Some(pid) = state.status.pid {
+                let pid = Pid::from_raw(pid as i32);
+                let _ = kill(pid, Signal::SIGTERM);
+
+                let (_state, timeout) = self
+                    .shared
+                    .changed
+                    .wait_timeout_while(state, STOP_TIMEOUT, |state| state.status.pid.is_some())
+                    .unwrap();
+                if timeout.timed_out() {
+                    let _ = kill(pid, Signal::SIGKILL);
+                }
+            }
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn supervise<F>(shared: &Shared, restart: Restart, mut child: Child, mut spawn: F)
+where
+    F: FnMut() -> Result<Child>,
+{
+    let mut failures = 0;
+
+    loop {
+        let exit_status = child.wait();
+        let success = matches!(exit_status, Ok(status) if status.success());
+        let uptime = {
+            let mut state = shared.state.lock().unwrap();
+            let uptime = state.status.set_exited(exit_status);
+            shared.changed.notify_all();
+            if state.stopping {
+                return;
+            }
+            uptime
+        };
+
+        match restart {
+            Restart::Always => {}
+            Restart::OnFailure if !success => {}
+            _ => return,
+        }
+
+        // back off if the child keeps exiting right after it was started.
+        if uptime < MIN_UPTIME {
+            failures += 1;
+        } else {
+            failures = 0;
+        }
+
+        child = loop {
+            let state = shared.state.lock().unwrap();
+            let (state, _) = shared
+                .changed
+                .wait_timeout_while(state, backoff(failures), |state| !state.stopping)
+                .unwrap();
+            if state.stopping {
+                return;
+            }
+            drop(state);
+
+            match spawn() {
+                Ok(child) => break child,
+                Err(e) => {
+                    failures += 1;
+                    shared
+                        .state
+                        .lock()
+                        .unwrap()
+                        .status
+                        .push_exit(Duration::ZERO, e.to_string());
+                }
+            }
+        };
+
+        let mut state = shared.state.lock().unwrap();
+        state.status.set_running(&child);
+        if state.stopping {
+            // stop() was called while the child was starting, so it didn't see the new pid.
+            let _ = child.kill();
+            let exit_status = child.wait();
+            state.status.set_exited(exit_status);
+            return;
+        }
+    }
+}
+
+pub trait StopWithParent {
+    /// Makes the child get killed when the daemon exits, so that it doesn't keep holding on to the
+    /// resources after the daemon is gone.
+    fn stop_with_parent(&mut self) -> &mut Self;
+}
+
+impl StopWithParent for Command {
+    fn stop_with_parent(&mut self) -> &mut Self {
+        unsafe {
+            self.pre_exec(|| {
+                if nix::libc::prctl(nix::libc::PR_SET_PDEATHSIG, nix::libc::SIGTERM) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs(1 << (failures - 1).min(6)).min(MAX_BACKOFF)
+}
+
+impl ChildStatus {
+    fn set_running(&mut self, child: &Child) {
+        self.pid = Some(child.id());
+        self.started = Some(unix_time(SystemTime::now()));
+    }
+
+    /// Returns how long the child was running.
+    fn set_exited(&mut self, exit_status: std::io::Result<ExitStatus>) -> Duration {
+        let uptime = self
+            .started
+            .map(|started| unix_time(SystemTime::now()).saturating_sub(started))
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+
+        self.pid = None;
+        self.started = None;
+
+        let status = match exit_status {
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        };
+        self.push_exit(uptime, status);
+
+        uptime
+    }
+
+    fn push_exit(&mut self, uptime: Duration, status: String) {
+        if self.exits.len() == MAX_HISTORY {
+            self.exits.remove(0);
+        }
+        self.exits.push(ChildExit {
+            time: unix_time(SystemTime::now()),
+            uptime: uptime.as_secs(),
+            status,
+        });
+    }
+}
+
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
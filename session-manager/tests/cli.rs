@@ -0,0 +1,517 @@
+// Copyright (c) 2022 John Ingve Olsen
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+
+/// Stands in for dbus-daemon, so that the tests don't depend on D-Bus being installed.
+const FAKE_DBUS_DAEMON: &str = r#"#!/bin/sh
+for arg in "$@"; do
+    case "$arg" in
+        --config-file=*) sed -n 's|.*<listen>\(.*\)</listen>.*|\1|p' "${arg#--config-file=}" ;;
+    esac
+done
+exec sleep 1000
+"#;
+
+/// A session manager that uses its own runtime directory, so that it doesn't interfere with the
+/// user's real session manager or with other tests.
+struct Session {
+    tmp: TempDir,
+    runtime_dir: PathBuf,
+    /// The `DBUS_SESSION_BUS_ADDRESS` that the session manager is started with.
+    bus_address: Option<String>,
+}
+
+impl Session {
+    fn new() -> Self {
+        let session = Self::without_runtime_dir();
+        fs::create_dir(&session.runtime_dir).unwrap();
+        // the runtime dir is only used if it is private.
+        fs::set_permissions(&session.runtime_dir, fs::Permissions::from_mode(0o700)).unwrap();
+        session
+    }
+
+    /// Points `XDG_RUNTIME_DIR` at a directory that doesn't exist yet.
+    fn without_runtime_dir() -> Self {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let bin_dir = tmp.path().join("bin");
+        fs::create_dir(&bin_dir).unwrap();
+        let dbus_daemon = bin_dir.join("dbus-daemon");
+        fs::write(&dbus_daemon, FAKE_DBUS_DAEMON).unwrap();
+        fs::set_permissions(&dbus_daemon, fs::Permissions::from_mode(0o755)).unwrap();
+
+        fs::create_dir(tmp.path().join("home")).unwrap();
+
+        Self {
+            runtime_dir: tmp.path().join("run"),
+            tmp,
+            bus_address: None,
+        }
+    }
+
+    fn with_bus_address(mut self, address: &str) -> Self {
+        self.bus_address = Some(address.to_string());
+        self
+    }
+
+    fn home(&self) -> PathBuf {
+        self.tmp.path().join("home")
+    }
+
+    fn write_config(&self, config: &str) {
+        let dir = self.home().join(".config/wsl-session-manager");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), config).unwrap();
+    }
+
+    fn add_service(&self, name: &str, config: &str) {
+        let dir = self.home().join(".config/wsl-session-manager/services");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{}.toml", name)), config).unwrap();
+    }
+
+    fn log_path(&self, name: &str) -> PathBuf {
+        self.home()
+            .join(".local/state/wsl-session-manager/logs")
+            .join(format!("{}.log", name))
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let path = format!(
+            "{}:{}",
+            self.tmp.path().join("bin").display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_wsl-session-mgr"));
+        command
+            .args(args)
+            .env("XDG_RUNTIME_DIR", &self.runtime_dir)
+            .env("PATH", path)
+            .env("HOME", self.home())
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("XDG_STATE_HOME");
+
+        match &self.bus_address {
+            Some(address) => command.env("DBUS_SESSION_BUS_ADDRESS", address),
+            None => command.env_remove("DBUS_SESSION_BUS_ADDRESS"),
+        };
+
+        command
+    }
+
+    fn run_ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn env(&self) -> BTreeMap<String, String> {
+        serde_json::from_str(&self.run_ok(&["env", "--shell", "json"])).unwrap()
+    }
+
+    fn bus_pid(&self) -> String {
+        self.env()["DBUS_SESSION_BUS_PID"].clone()
+    }
+
+    fn socket_path(&self) -> PathBuf {
+        self.runtime_dir.join("wsl-session-manager.sock")
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.run(&["stop"]);
+    }
+}
+
+fn is_running(pid: &str) -> bool {
+    // zombies still have a /proc entry, but are not running.
+    match fs::read_to_string(Path::new("/proc").join(pid).join("stat")) {
+        Ok(stat) => !stat.contains(") Z "),
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn status_when_not_running() {
+    let session = Session::new();
+    let output = session.run(&["status"]);
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(!session.socket_path().exists());
+}
+
+#[test]
+fn env_starts_daemon() {
+    let session = Session::new();
+    let env = session.env();
+
+    assert_eq!(
+        env["DBUS_SESSION_BUS_ADDRESS"],
+        format!("unix:path={}", session.runtime_dir.join("bus").display())
+    );
+    assert!(is_running(&env["DBUS_SESSION_BUS_PID"]));
+
+    // the default output is for sh.
+    let output = session.run_ok(&[]);
+    assert!(output.contains("export DBUS_SESSION_BUS_ADDRESS;"));
+}
+
+#[test]
+fn runtime_dir_is_reused() {
+    let session = Session::new();
+    fs::write(session.runtime_dir.join("wayland-0"), "").unwrap();
+
+    let env = session.env();
+    assert_eq!(
+        env["XDG_RUNTIME_DIR"],
+        session.runtime_dir.display().to_string()
+    );
+
+    // a runtime dir that we didn't create is left alone.
+    session.run_ok(&["stop"]);
+    assert!(session.runtime_dir.join("wayland-0").exists());
+    // but the files that we generated are removed.
+    assert!(!session.runtime_dir.join("wsl-session-manager").exists());
+}
+
+#[test]
+fn runtime_dir_is_created() {
+    let session = Session::without_runtime_dir();
+
+    let env = session.env();
+    assert_eq!(
+        env["XDG_RUNTIME_DIR"],
+        session.runtime_dir.display().to_string()
+    );
+    let metadata = fs::metadata(&session.runtime_dir).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+
+    session.run_ok(&["stop"]);
+    assert!(!session.runtime_dir.exists());
+
+    // looking for the daemon doesn't create it again.
+    assert_eq!(session.run(&["status"]).status.code(), Some(3));
+    assert!(!session.runtime_dir.exists());
+}
+
+#[test]
+fn bus_config() {
+    let session = Session::new();
+    let bridge = session.tmp.path().join("bin/xdp-wsl-bridge");
+    fs::write(&bridge, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&bridge, fs::Permissions::from_mode(0o755)).unwrap();
+    session.env();
+
+    let generated_dir = session.runtime_dir.join("wsl-session-manager");
+    let config = fs::read_to_string(generated_dir.join("session.conf")).unwrap();
+    assert!(config.contains(&format!(
+        "<listen>unix:path={}</listen>",
+        session.runtime_dir.join("bus").display()
+    )));
+    assert!(config.contains(&format!(
+        "<include ignore_missing=\"yes\">{}</include>",
+        session
+            .home()
+            .join(".config/wsl-session-manager/dbus.conf")
+            .display()
+    )));
+
+    for name in [
+        "org.freedesktop.impl.portal.desktop.wsl",
+        "com.github.raytar.Icons",
+        "com.github.raytar.WSL",
+    ] {
+        let service =
+            fs::read_to_string(generated_dir.join(format!("services/{}.service", name))).unwrap();
+        assert_eq!(
+            service,
+            format!(
+                "[D-BUS Service]\nName={}\nExec={}\n",
+                name,
+                bridge.display()
+            )
+        );
+    }
+}
+
+#[test]
+fn status() {
+    let session = Session::new();
+    let bus_pid = session.bus_pid();
+
+    let status = session.run_ok(&["status"]);
+    assert!(status.contains("wsl-session-mgr: running"), "{}", status);
+    assert!(
+        status.contains(&format!("dbus-daemon: running (pid {},", bus_pid)),
+        "{}",
+        status
+    );
+}
+
+#[test]
+fn stop() {
+    let session = Session::new();
+    let bus_pid = session.bus_pid();
+
+    session.run_ok(&["stop"]);
+    assert!(!is_running(&bus_pid));
+    assert!(!session.socket_path().exists());
+    assert_eq!(session.run(&["status"]).status.code(), Some(3));
+
+    // stopping a daemon that isn't running is not an error.
+    session.run_ok(&["stop"]);
+}
+
+#[test]
+fn restart() {
+    let session = Session::new();
+    let bus_pid = session.bus_pid();
+
+    let status = session.run_ok(&["restart"]);
+    assert!(status.contains("dbus-daemon: running"), "{}", status);
+
+    let new_bus_pid = session.bus_pid();
+    assert_ne!(bus_pid, new_bus_pid);
+    assert!(!is_running(&bus_pid));
+    assert!(is_running(&new_bus_pid));
+}
+
+#[test]
+fn bus_is_restarted() {
+    let session = Session::new();
+    let bus_pid = session.bus_pid();
+
+    Command::new("kill").arg(&bus_pid).status().unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let env = session.env();
+        if let Some(pid) = env.get("DBUS_SESSION_BUS_PID") {
+            if *pid != bus_pid {
+                assert!(is_running(pid));
+                break;
+            }
+        }
+        assert!(Instant::now() < deadline, "dbus-daemon was not restarted");
+        sleep(Duration::from_millis(100));
+    }
+
+    let status = session.run_ok(&["status"]);
+    assert!(status.contains("ago: signal: 15"), "{}", status);
+}
+
+#[test]
+fn protocol_version_mismatch() {
+    let session = Session::new();
+    session.env();
+
+    let mut stream = UnixStream::connect(session.socket_path()).unwrap();
+    stream
+        .write_all(b"{\"version\":999,\"command\":\"status\"}\n")
+        .unwrap();
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+    assert_eq!(response["version"], 1);
+    assert!(response["reply"]["error"]
+        .as_str()
+        .unwrap()
+        .contains("version mismatch"));
+}
+
+/// Waits for a file to contain `text`, and returns its contents.
+fn wait_for_contents(path: &Path, text: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        if contents.contains(text) {
+            return contents;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "{} does not contain {:?}: {:?}",
+            path.display(),
+            text,
+            contents
+        );
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn services() {
+    let session = Session::new();
+    // the fake dbus-daemon doesn't create the bus socket.
+    session.add_service(
+        "echo",
+        r#"
+            command = ["sh", "-c", "echo \"$GREETING $DBUS_SESSION_BUS_ADDRESS\"; exec sleep 1000"]
+            environment = { GREETING = "hello" }
+            depends-on-bus = false
+        "#,
+    );
+    session.add_service(
+        "oneshot",
+        r#"
+            command = ["true"]
+            depends-on-bus = false
+        "#,
+    );
+    session.add_service("broken", "command = []");
+
+    let env = session.env();
+    wait_for_contents(
+        &session.log_path("echo"),
+        &format!("hello {}\n", env["DBUS_SESSION_BUS_ADDRESS"]),
+    );
+
+    let status = session.run_ok(&["status"]);
+    assert!(status.contains("\necho: running"), "{}", status);
+    // services that exit successfully are not restarted by default.
+    assert!(status.contains("\noneshot: not running"), "{}", status);
+    assert!(
+        status.contains("broken.toml: command must not be empty"),
+        "{}",
+        status
+    );
+}
+
+#[test]
+fn service_is_restarted() {
+    let session = Session::new();
+    session.add_service(
+        "crash",
+        r#"
+            command = ["sh", "-c", "echo started; exit 1"]
+            depends-on-bus = false
+        "#,
+    );
+
+    session.env();
+    wait_for_contents(&session.log_path("crash"), "started\nstarted\n");
+
+    let status = session.run_ok(&["status"]);
+    assert!(status.contains("exit status: 1"), "{}", status);
+}
+
+#[test]
+fn existing_bus_is_reused() {
+    let session = Session::new();
+    // like the systemd user bus.
+    let bus_socket = session.runtime_dir.join("bus");
+    let _bus = UnixListener::bind(&bus_socket).unwrap();
+
+    let env = session.env();
+    assert_eq!(
+        env["DBUS_SESSION_BUS_ADDRESS"],
+        format!("unix:path={}", bus_socket.display())
+    );
+    // the bus is not ours, so nobody should be told to kill it.
+    assert!(!env.contains_key("DBUS_SESSION_BUS_PID"));
+
+    let status = session.run_ok(&["status"]);
+    assert!(
+        status.contains(&format!(", pid {})", std::process::id())),
+        "{}",
+        status
+    );
+    assert!(!status.contains("dbus-daemon"), "{}", status);
+
+    session.run_ok(&["stop"]);
+    assert!(bus_socket.exists());
+}
+
+#[test]
+fn bus_address_is_reused() {
+    let tmp = tempfile::tempdir().unwrap();
+    let bus_socket = tmp.path().join("bus");
+    let _bus = UnixListener::bind(&bus_socket).unwrap();
+    let address = format!("unix:path={},guid=0123456789abcdef", bus_socket.display());
+
+    let session = Session::new().with_bus_address(&address);
+    let env = session.env();
+    assert_eq!(env["DBUS_SESSION_BUS_ADDRESS"], address);
+    assert!(!env.contains_key("DBUS_SESSION_BUS_PID"));
+}
+
+#[test]
+fn unreachable_bus_address_is_ignored() {
+    let session = Session::new().with_bus_address("unix:path=/nonexistent/bus");
+    let env = session.env();
+
+    assert_eq!(
+        env["DBUS_SESSION_BUS_ADDRESS"],
+        format!("unix:path={}", session.runtime_dir.join("bus").display())
+    );
+    assert!(env.contains_key("DBUS_SESSION_BUS_PID"));
+}
+
+#[test]
+fn sessions() {
+    let session = Session::new();
+    session.env();
+
+    let status = session.run_ok(&["status"]);
+    let sid = nix::unistd::getsid(None).unwrap();
+    assert!(
+        status.contains(&format!("sessions: {}\n", sid)),
+        "{}",
+        status
+    );
+    // the daemon runs forever by default.
+    assert!(status.contains("idle timeout: never"), "{}", status);
+}
+
+#[test]
+fn idle_shutdown() {
+    let session = Session::new();
+    session.write_config("idle-timeout = 1");
+
+    // run the client in a session that ends as soon as it exits, like a closed terminal.
+    let output = unsafe {
+        session
+            .command(&["env"])
+            .pre_exec(|| {
+                nix::unistd::setsid()?;
+                Ok(())
+            })
+            .output()
+            .unwrap()
+    };
+    assert!(output.status.success());
+    assert!(session.socket_path().exists());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while session.socket_path().exists() {
+        assert!(Instant::now() < deadline, "the daemon did not shut down");
+        sleep(Duration::from_millis(100));
+    }
+    assert_eq!(session.run(&["status"]).status.code(), Some(3));
+}